@@ -1,8 +1,33 @@
 #[cfg(not(debug_assertions))]
 use static_files::resource_dir;
 
+/// Shown in the admin status, `GIT_COMMIT` can be set where the `.git` directory isn't available.
+fn build_info() {
+    println!("cargo::rerun-if-env-changed=GIT_COMMIT");
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(|| {
+        let output = std::process::Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    });
+    if let Some(commit) = commit.filter(|commit| !commit.is_empty()) {
+        println!("cargo::rustc-env=BUILD_COMMIT={commit}");
+    }
+    for (name, var) in [("BUILD_PROFILE", "PROFILE"), ("BUILD_TARGET", "TARGET")] {
+        println!(
+            "cargo::rustc-env={name}={}",
+            std::env::var(var).unwrap_or_default()
+        );
+    }
+}
+
 fn main() -> std::io::Result<()> {
     println!("cargo::rerun-if-changed=./frontend");
+    build_info();
     #[cfg(not(debug_assertions))]
     return resource_dir("./frontend/build").build();
     #[cfg(debug_assertions)]
@@ -12,6 +12,14 @@ declare global {
 export type User = {
     id: string;
     name: string;
+    guest: boolean;
+};
+
+export type Preferences = {
+    displayName: string | null;
+    subtitleLanguage: string | null;
+    syncTolerance: number;
+    chatNotifications: boolean;
 };
 
 export enum MouseButton {
@@ -76,15 +84,85 @@ export function getLocalTimestamp(d: Date): string {
     );
 }
 
+// same rules as `redirect::validate` on the server: only paths on this site, never `//host`
+// or `/\host`, with `.` and `..` segments resolved and `..` above the root rejected
+export function safeRedirect(target: string | null): string {
+    if (
+        target == null ||
+        !target.startsWith("/") ||
+        target.startsWith("//") ||
+        /[\\\s\x00-\x1f\x7f-\x9f]/.test(target)
+    ) {
+        return "/";
+    }
+
+    let index = target.search(/[?#]/);
+    let path = index == -1 ? target : target.slice(0, index);
+    let rest = index == -1 ? "" : target.slice(index);
+    let segments: string[] = [];
+    for (let segment of path.split("/")) {
+        if (segment == "" || segment == ".") {
+            continue;
+        } else if (segment == "..") {
+            if (segments.pop() == undefined) {
+                return "/";
+            }
+        } else {
+            segments.push(segment);
+        }
+    }
+    return "/" + segments.join("/") + rest;
+}
+
+// problem+json body of a failed API request
+export type ApiError = {
+    status: number;
+    title: string;
+    detail: string;
+    code: string;
+    requestId: string | null;
+    details?: Record<string, unknown>;
+};
+
+export async function apiError(res: Response): Promise<ApiError> {
+    let text = await res.text();
+    if (res.headers.get("content-type")?.startsWith("application/problem+json")) {
+        try {
+            return JSON.parse(text);
+        } catch {
+            // fall through to the plain text
+        }
+    }
+    return {
+        status: res.status,
+        title: res.statusText,
+        detail: text || res.statusText,
+        code: "unknown",
+        requestId: res.headers.get("x-request-id")
+    };
+}
+
+// for showing to users, server errors include the request id to quote when reporting them
+export async function errorMessage(res: Response): Promise<string> {
+    let error = await apiError(res);
+    if (error.status >= 500 && error.requestId != null) {
+        return `${error.detail} (request ${error.requestId})`;
+    }
+    return error.detail;
+}
+
 export class RoomClient {
     id: number = 0;
     userId: number = 0;
     name: string = "";
+    guest: boolean = false;
 }
 
 export class Room {
     id: number = 0;
     name: string = "";
+    owner: number = 0;
+    allowGuests: boolean = false;
     members: RoomClient[] = [];
 }
 
@@ -93,5 +171,16 @@ export enum PlayerCommands {
     Play,
     Pause,
     UpdateTime,
-    SetTime
+    SetTime,
+    SetMedia,
+    Subtitles,
+    Warning,
+    Restarting
+}
+
+export class SubtitleTrack {
+    id: number = 0;
+    label: string = "";
+    language: string | null = null;
+    url: string = "";
 }
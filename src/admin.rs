@@ -0,0 +1,309 @@
+use {
+    crate::{
+        clock::timestamp,
+        error::{bad_request, forbidden, not_found, ToErr},
+        health::{self, Check},
+        room::{self, Room},
+        storage,
+        telemetry::LogFilter,
+        tokens::Scope,
+        user::SessionUser,
+        users::Role,
+        AppData,
+    },
+    actix_web::{delete, get, put, web, Error, HttpResponse, Responder},
+    serde::{Deserialize, Serialize},
+    std::{
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+const BANS_FILE: &str = "bans.json";
+
+/// Admins are listed in `ADMIN_USERS` or were given the role, by another admin or their provider.
+pub fn is_admin(data: &AppData, user_id: u32) -> bool {
+    data.admins.contains(&user_id) || data.users.role(user_id) == Role::Admin
+}
+
+pub fn require_admin(data: &AppData, user: &SessionUser) -> Result<(), Error> {
+    user.require(Scope::Admin)?;
+    match is_admin(data, user.id) {
+        true => Ok(()),
+        false => Err(forbidden("admins_only", "Admins only")),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+    pub user_id: u32,
+    pub reason: Option<String>,
+    /// The admin who banned the user.
+    pub by: u32,
+    /// Milliseconds since the unix epoch.
+    pub created: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct BansFile {
+    bans: Vec<Ban>,
+}
+
+/// Users banned from the whole instance.
+pub struct Bans {
+    path: PathBuf,
+    file: Mutex<BansFile>,
+}
+
+impl Bans {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(BANS_FILE);
+        Ok(Self {
+            file: Mutex::new(storage::load(&path)?),
+            path,
+        })
+    }
+
+    pub fn is_banned(&self, user_id: u32) -> bool {
+        let file = self.file.lock().unwrap();
+        file.bans.iter().any(|ban| ban.user_id == user_id)
+    }
+
+    pub fn list(&self) -> Vec<Ban> {
+        self.file.lock().unwrap().bans.clone()
+    }
+
+    fn ban(&self, ban: Ban) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.bans.retain(|x| x.user_id != ban.user_id);
+        file.bans.push(ban);
+        storage::save(&self.path, &*file)
+    }
+
+    fn unban(&self, user_id: u32) -> std::io::Result<bool> {
+        let mut file = self.file.lock().unwrap();
+        let count = file.bans.len();
+        file.bans.retain(|ban| ban.user_id != user_id);
+        if file.bans.len() == count {
+            return Ok(false);
+        }
+        storage::save(&self.path, &*file)?;
+        Ok(true)
+    }
+}
+
+/// Every room.
+#[get("/api/admin/rooms")]
+async fn list_rooms(data: web::Data<AppData>, user: SessionUser) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+
+    let rooms_guard = data.rooms.read().await;
+    let mut rooms = Vec::with_capacity(rooms_guard.len());
+    for room in rooms_guard.values() {
+        rooms.push(room.read().await);
+    }
+    rooms.sort_by_key(|room| room.id());
+    Ok(HttpResponse::Ok().json(rooms.iter().map(|room| &**room).collect::<Vec<&Room>>()))
+}
+
+#[delete("/api/admin/rooms/{id}")]
+async fn delete_room(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    let id = id.into_inner();
+    match room::delete(&data, id).await {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(not_found(
+            "room_not_found",
+            format!("Room with id {id} not found"),
+        )),
+    }
+}
+
+#[delete("/api/admin/rooms/{id}/sockets/{ws_id}")]
+async fn disconnect_socket(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    path: web::Path<(u32, u32)>,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    let (id, ws_id) = path.into_inner();
+    match room::disconnect_socket(&data, id, ws_id).await {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(not_found(
+            "socket_not_found",
+            format!("Socket {ws_id} not found in room {id}"),
+        )),
+    }
+}
+
+#[get("/api/admin/bans")]
+async fn list_bans(data: web::Data<AppData>, user: SessionUser) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    Ok(web::Json(data.bans.list()))
+}
+
+#[derive(Deserialize)]
+struct NewBan {
+    reason: Option<String>,
+}
+
+/// Bans a user, ending their sessions and disconnecting them from every room.
+#[put("/api/admin/bans/{user_id}")]
+async fn ban_user(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    user_id: web::Path<u32>,
+    body: web::Json<NewBan>,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    let user_id = user_id.into_inner();
+    if user_id == user.id {
+        return Err(forbidden("self_ban", "You can't ban yourself"));
+    }
+
+    let ban = Ban {
+        user_id,
+        reason: body.into_inner().reason,
+        by: user.id,
+        created: timestamp(),
+    };
+    data.bans.ban(ban.clone()).to_err()?;
+    data.sessions.revoke_user(user_id);
+    room::disconnect_user(&data, user_id, "Banned").await;
+    Ok(web::Json(ban))
+}
+
+#[delete("/api/admin/bans/{user_id}")]
+async fn unban_user(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    user_id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    match data.bans.unban(user_id.into_inner()).to_err()? {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(not_found("user_not_banned", "User is not banned")),
+    }
+}
+
+#[derive(Deserialize)]
+struct SetRole {
+    role: Role,
+}
+
+#[put("/api/admin/users/{id}/role")]
+async fn set_role(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+    body: web::Json<SetRole>,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    data.users.set_role(id.into_inner(), body.role).to_err()?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Build {
+    version: &'static str,
+    commit: Option<&'static str>,
+    profile: &'static str,
+    target: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    ready: bool,
+    checks: Vec<Check>,
+    rooms: usize,
+    sockets: usize,
+    /// Seconds since the server started.
+    uptime: u64,
+    build: Build,
+}
+
+#[get("/api/admin/status")]
+async fn status(data: web::Data<AppData>, user: SessionUser) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+
+    let checks = health::checks(&data).await;
+    let rooms_guard = data.rooms.read().await;
+    let mut sockets = 0;
+    for room in rooms_guard.values() {
+        sockets += room.read().await.socket_count();
+    }
+    Ok(web::Json(Status {
+        ready: checks.iter().all(|check| check.error.is_none()),
+        checks,
+        rooms: rooms_guard.len(),
+        sockets,
+        uptime: data.started.elapsed().as_secs(),
+        build: Build {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("BUILD_COMMIT"),
+            profile: env!("BUILD_PROFILE"),
+            target: env!("BUILD_TARGET"),
+        },
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogLevel {
+    /// Filter directives like `info,sync_play=debug`.
+    level: String,
+}
+
+fn log_filter(data: &AppData) -> Result<&LogFilter, Error> {
+    data.log_filter.as_ref().ok_or_else(|| {
+        not_found(
+            "log_level_unmanaged",
+            "Logging is not managed by this server",
+        )
+    })
+}
+
+#[get("/api/admin/log-level")]
+async fn get_log_level(
+    data: web::Data<AppData>,
+    user: SessionUser,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    Ok(web::Json(LogLevel {
+        level: log_filter(&data)?.current(),
+    }))
+}
+
+/// Lasts until the next restart, which uses `log.level` again.
+#[put("/api/admin/log-level")]
+async fn set_log_level(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    body: web::Json<LogLevel>,
+) -> Result<impl Responder, Error> {
+    require_admin(&data, &user)?;
+    log_filter(&data)?
+        .set(&body.level)
+        .map_err(|err| bad_request("invalid_log_level", err))?;
+    tracing::info!(level = body.level, user_id = user.id, "Log level changed");
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(status);
+    cfg.service(get_log_level);
+    cfg.service(set_log_level);
+    cfg.service(list_rooms);
+    cfg.service(delete_room);
+    cfg.service(disconnect_socket);
+    cfg.service(list_bans);
+    cfg.service(ban_user);
+    cfg.service(unban_user);
+    cfg.service(set_role);
+}
@@ -1,76 +1,117 @@
 use {
-    std::{cell::UnsafeCell, collections::HashMap, mem::MaybeUninit},
+    crate::{
+        admin::Bans,
+        auth::{local::LocalAccounts, AuthProviders},
+        config::Config,
+        media::MediaLibrary,
+        metrics::Metrics,
+        pictures::PictureCache,
+        preferences::UserPreferences,
+        rate_limit::{RateLimits, UserLimiter},
+        room::Room,
+        sessions::ServerSessionStore,
+        telemetry::LogFilter,
+        tokens::Tokens,
+        users::Users,
+    },
+    std::{
+        collections::HashMap,
+        net::IpAddr,
+        path::PathBuf,
+        sync::{atomic::AtomicBool, Arc},
+        time::{Duration, Instant},
+    },
     tokio::sync::RwLock,
-    crate::room::Room,
 };
 
-static APP_DATA: InitOnce<AppData> = InitOnce::new();
-
-struct InitOnce<T>(UnsafeCell<MaybeUninit<T>>);
-
-impl<T> InitOnce<T> {
-    pub const fn new() -> Self {
-        Self(UnsafeCell::new(MaybeUninit::uninit()))
-    }
-
-    pub unsafe fn init(&self, data: T) {
-        *self.0.get() = MaybeUninit::new(data);
-    }
-
-    pub fn get(&self) -> &T {
-        unsafe { (*self.0.get()).assume_init_ref() }
-    }
-}
-
-unsafe impl<T> Send for InitOnce<T> {}
-unsafe impl<T> Sync for InitOnce<T> {}
-
+/// Everything the handlers share, one per server instance.
+///
+/// Registered with `App::app_data` and taken by handlers as `web::Data<AppData>`.
 pub struct AppData {
-    pub authentication_service: authentication_service::Client,
+    pub auth_providers: AuthProviders,
+    pub users: Users,
+    pub preferences: UserPreferences,
+    pub local_accounts: Option<Arc<LocalAccounts>>,
+    pub sessions: ServerSessionStore,
+    pub tokens: Tokens,
+    /// User ids that are always admins.
+    pub admins: Vec<u32>,
+    pub bans: Bans,
     pub rooms: RwLock<HashMap<u32, RwLock<Room>>>,
+    pub media: Option<MediaLibrary>,
+    pub pictures: PictureCache,
+    pub rate_limits: RateLimits,
+    pub room_creation_limiter: UserLimiter,
+    pub subtitle_upload_limiter: UserLimiter,
+    pub login_limiter: UserLimiter<IpAddr>,
+    /// How often the latest `UpdateTime` of each room is broadcast.
+    pub time_update_tick: Duration,
+    /// Set once shutdown begins, rooms stop accepting members.
+    pub shutting_down: AtomicBool,
+    pub data_dir: PathBuf,
+    pub started: Instant,
+    pub metrics: Arc<Metrics>,
+    /// Set if this instance installed the global subscriber.
+    pub log_filter: Option<LogFilter>,
 }
 
 impl AppData {
-    pub async fn new() -> Self {
-        let authentication_service = {
-            let server_key = std::env::var("AUTH_SERVER_KEY")
-                .unwrap_or_else(|_| String::from("auth_server.pem"));
-
-            let private_key =
-                std::env::var("PRIVATE_KEY").unwrap_or_else(|_| String::from("private.pem"));
-
-            let server_key = match std::fs::read(&*shellexpand::tilde(&server_key)) {
-                Ok(x) => x,
-                Err(err) => panic!("Unable to open {server_key:?}: {err:?}"),
-            };
-
-            let private_key = match std::fs::read(&*shellexpand::tilde(&private_key)) {
-                Ok(x) => x,
-                Err(err) => panic!("Unable to open {private_key:?}: {err:?}"),
-            };
-
-            let host = std::env::var("AUTH_SERVER_HOST")
-                .expect("Missing environment variable AUTH_SERVER_HOST");
-
-            let server_id = std::env::var("AUTH_SERVER_ID")
-                .expect("Missing environment variable AUTH_SERVER_ID")
-                .parse()
-                .expect("Invalid environment variable AUTH_SERVER_ID");
-
-            authentication_service::Client::new(server_id, &private_key, host, &server_key).unwrap()
+    /// Fails with every file that couldn't be loaded, not just the first one.
+    pub fn new(config: &Config) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+        let auth_providers =
+            AuthProviders::from_config(&config.auth).map_err(|err| errors.push(err));
+        let users = Users::load(&config.data_dir).map_err(|err| errors.push(err));
+        let preferences = UserPreferences::load(&config.data_dir).map_err(|err| errors.push(err));
+        let sessions = ServerSessionStore::new(config.session.store, &config.data_dir)
+            .map_err(|err| errors.push(err));
+        let tokens = Tokens::load(&config.data_dir).map_err(|err| errors.push(err));
+        let bans = Bans::load(&config.data_dir).map_err(|err| errors.push(err));
+        let media = MediaLibrary::from_config(&config.media).map_err(|err| errors.push(err));
+        let (
+            Ok(auth_providers),
+            Ok(users),
+            Ok(preferences),
+            Ok(sessions),
+            Ok(tokens),
+            Ok(bans),
+            Ok(media),
+        ) = (
+            auth_providers,
+            users,
+            preferences,
+            sessions,
+            tokens,
+            bans,
+            media,
+        )
+        else {
+            return Err(errors);
         };
 
-        Self {
-            authentication_service,
+        Ok(Self {
+            auth_providers,
+            users,
+            preferences,
+            local_accounts: LocalAccounts::from_config(&config.auth.local, &config.data_dir)
+                .map(Arc::new),
+            sessions,
+            tokens,
+            admins: config.admin_users.clone(),
+            bans,
             rooms: RwLock::new(HashMap::new()),
-        }
-    }
-
-    pub async unsafe fn init() {
-        APP_DATA.init(Self::new().await)
-    }
-
-    pub fn get() -> &'static Self {
-        APP_DATA.get()
+            media,
+            pictures: PictureCache::from_config(&config.pictures, &config.data_dir),
+            room_creation_limiter: UserLimiter::new(config.rate_limits.room_creation),
+            subtitle_upload_limiter: UserLimiter::new(config.rate_limits.subtitle_upload),
+            login_limiter: UserLimiter::new(config.rate_limits.login),
+            rate_limits: config.rate_limits.clone(),
+            time_update_tick: Duration::from_millis(config.time_update_tick_ms),
+            shutting_down: AtomicBool::new(false),
+            data_dir: config.data_dir.clone(),
+            started: Instant::now(),
+            metrics: Arc::new(Metrics::new(&config.metrics)),
+            log_filter: None,
+        })
     }
 }
@@ -0,0 +1,228 @@
+use {
+    crate::{
+        config::AuthConfig,
+        error::{bad_gateway, ToErr},
+        user::SessionUser,
+        users::Users,
+    },
+    actix_session::Session,
+    actix_web::Error,
+    async_trait::async_trait,
+    serde::Serialize,
+    std::{collections::HashMap, time::Duration},
+};
+
+/// Larger profile pictures are rejected.
+const MAX_PICTURE_SIZE: u64 = 5 * 1024 * 1024;
+const PICTURE_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// For the whole download, including connecting.
+const PICTURE_TIMEOUT: Duration = Duration::from_secs(15);
+
+lazy_static::lazy_static! {
+    /// Shared by every picture download, so connections to providers are reused.
+    static ref PICTURE_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(PICTURE_CONNECT_TIMEOUT)
+        .timeout(PICTURE_TIMEOUT)
+        .build()
+        .expect("Unable to create the picture HTTP client");
+}
+
+mod authentication_service;
+pub mod dev;
+pub mod local;
+mod oidc;
+
+pub use {
+    authentication_service::AuthenticationServiceProvider, dev::DevProvider, local::LocalProvider,
+    oidc::OidcProvider,
+};
+
+/// A way for users to log in. Every provider gets its own `/auth/providers/{name}` routes.
+#[async_trait(?Send)]
+pub trait AuthProvider: Send + Sync {
+    /// Unique, url safe name of the provider.
+    fn name(&self) -> &str;
+
+    /// Name shown on the login page.
+    fn display_name(&self) -> &str;
+
+    /// Checks that logins can currently go through, for `/readyz`.
+    /// Providers that don't depend on another service are always ready.
+    async fn check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Where to send the user to start logging in.
+    async fn login_url(&self, session: &Session) -> Result<String, Error>;
+
+    /// Exchanges the query of the provider's callback for a user.
+    /// Returns `None` if the login has to be restarted.
+    async fn callback(
+        &self,
+        users: &Users,
+        query: &HashMap<String, String>,
+        session: &Session,
+    ) -> Result<Option<SessionUser>, Error>;
+
+    /// Returns `None` if the provider doesn't know the user or has no picture for them.
+    fn profile_picture_url(&self, users: &Users, user_id: u32) -> Option<String>;
+
+    /// Downloads the user's profile picture, by default from [`Self::profile_picture_url`].
+    async fn profile_picture(&self, users: &Users, user_id: u32) -> Result<Option<Vec<u8>>, Error> {
+        let Some(url) = self.profile_picture_url(users, user_id) else {
+            return Ok(None);
+        };
+        let response = PICTURE_CLIENT.get(url).send().await.to_err()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let mut response = response.error_for_status().to_err()?;
+        let too_large = || bad_gateway("picture_too_large", "Profile picture too large");
+        if response.content_length().unwrap_or(0) > MAX_PICTURE_SIZE {
+            return Err(too_large());
+        }
+        // the length may be missing or wrong, so the body is counted as it arrives
+        let mut picture = Vec::new();
+        while let Some(chunk) = response.chunk().await.to_err()? {
+            if (picture.len() + chunk.len()) as u64 > MAX_PICTURE_SIZE {
+                return Err(too_large());
+            }
+            picture.extend_from_slice(&chunk);
+        }
+        Ok(Some(picture))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderInfo<'a> {
+    name: &'a str,
+    display_name: &'a str,
+}
+
+/// All configured providers, the first one being the default.
+pub struct AuthProviders(Vec<Box<dyn AuthProvider>>);
+
+impl AuthProviders {
+    /// The configuration is validated to enable at least one provider.
+    pub fn from_config(config: &AuthConfig) -> Result<Self, String> {
+        let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+        // first, so `/auth` goes straight to the picker
+        if let Some(provider) = DevProvider::from_config(config) {
+            providers.push(Box::new(provider));
+        }
+        if let Some(provider) = AuthenticationServiceProvider::from_config(&config.auth_server)? {
+            providers.push(Box::new(provider));
+        }
+        if let Some(provider) = OidcProvider::from_config(&config.oidc) {
+            providers.push(Box::new(provider));
+        }
+        if config.local.enabled {
+            providers.push(Box::new(LocalProvider));
+        }
+        Ok(Self(providers))
+    }
+
+    /// `None` only if the configuration wasn't validated.
+    pub fn default_provider(&self) -> Option<&dyn AuthProvider> {
+        self.0.first().map(|provider| &**provider)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn AuthProvider> {
+        self.0
+            .iter()
+            .find(|provider| provider.name() == name)
+            .map(|provider| &**provider)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn AuthProvider> {
+        self.0.iter().map(|provider| &**provider)
+    }
+
+    pub fn info(&self) -> Vec<ProviderInfo<'_>> {
+        self.iter()
+            .map(|provider| ProviderInfo {
+                name: provider.name(),
+                display_name: provider.display_name(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::error::ApiError,
+        actix_web::{rt, web, App, HttpResponse, HttpServer},
+        futures_util::stream,
+    };
+
+    /// Serves pictures from a local server.
+    struct PictureProvider(String);
+
+    #[async_trait(?Send)]
+    impl AuthProvider for PictureProvider {
+        fn name(&self) -> &str {
+            "pictures"
+        }
+
+        fn display_name(&self) -> &str {
+            "Pictures"
+        }
+
+        async fn login_url(&self, _session: &Session) -> Result<String, Error> {
+            unimplemented!()
+        }
+
+        async fn callback(
+            &self,
+            _users: &Users,
+            _query: &HashMap<String, String>,
+            _session: &Session,
+        ) -> Result<Option<SessionUser>, Error> {
+            unimplemented!()
+        }
+
+        fn profile_picture_url(&self, _users: &Users, user_id: u32) -> Option<String> {
+            Some(format!("{}/{user_id}", self.0))
+        }
+    }
+
+    fn provider() -> PictureProvider {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/{size}",
+                web::get().to(|size: web::Path<u64>| async move {
+                    // chunked, without a length to check up front
+                    let chunk = web::Bytes::from(vec![0; 1024 * 1024]);
+                    let chunks = (0..*size).map(move |_| Ok::<_, Error>(chunk.clone()));
+                    HttpResponse::Ok().streaming(stream::iter(chunks))
+                }),
+            )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        rt::spawn(server);
+        PictureProvider(url)
+    }
+
+    #[actix_web::test]
+    async fn caps_picture_downloads() {
+        let dir = std::env::temp_dir().join(format!("sync-play-auth-{}", std::process::id()));
+        let users = Users::load(&dir).unwrap();
+        let provider = provider();
+
+        let picture = provider.profile_picture(&users, 2).await.unwrap().unwrap();
+        assert_eq!(picture.len(), 2 * 1024 * 1024);
+        let err = provider.profile_picture(&users, 6).await.unwrap_err();
+        assert_eq!(
+            err.as_error::<ApiError>().unwrap().code(),
+            "picture_too_large"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use {
+    super::AuthProvider,
+    crate::{
+        config::{self, AuthServerConfig},
+        error::{bad_request, ToErr},
+        user::SessionUser,
+        users::Users,
+    },
+    actix_session::Session,
+    actix_web::Error,
+    async_trait::async_trait,
+    std::{collections::HashMap, path::PathBuf, time::Duration},
+};
+
+/// Login through the riseupgroup authentication service.
+pub struct AuthenticationServiceProvider {
+    client: authentication_service::Client,
+    host: String,
+}
+
+impl AuthenticationServiceProvider {
+    /// Returns `None` if `auth.auth_server.host` is not set.
+    pub fn from_config(config: &AuthServerConfig) -> Result<Option<Self>, String> {
+        let Some(host) = config.host.clone() else {
+            return Ok(None);
+        };
+
+        let read = |path: &PathBuf| {
+            std::fs::read(config::expand(path))
+                .map_err(|err| format!("Unable to open {path:?}: {err}"))
+        };
+        let server_key = read(&config.key)?;
+        let private_key = read(&config.private_key)?;
+
+        let server_id = config.id.expect("Missing auth.auth_server.id");
+
+        let client =
+            authentication_service::Client::new(server_id, &private_key, host.clone(), &server_key)
+                .map_err(|err| format!("Invalid auth.auth_server keys: {err:?}"))?;
+        Ok(Some(Self { client, host }))
+    }
+}
+
+#[async_trait(?Send)]
+impl AuthProvider for AuthenticationServiceProvider {
+    fn name(&self) -> &str {
+        "auth_server"
+    }
+
+    fn display_name(&self) -> &str {
+        "Auth Server"
+    }
+
+    /// Any response means the server is reachable, the client has no dedicated health check.
+    async fn check(&self) -> Result<(), Error> {
+        reqwest::Client::new()
+            .get(format!("https://{}/", self.host))
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+            .map(|_| ())
+            .to_err()
+    }
+
+    async fn login_url(&self, _session: &Session) -> Result<String, Error> {
+        Ok(self.client.get_redirect_url())
+    }
+
+    async fn callback(
+        &self,
+        users: &Users,
+        query: &HashMap<String, String>,
+        _session: &Session,
+    ) -> Result<Option<SessionUser>, Error> {
+        let id = match query.get("id") {
+            Some(id) => id
+                .parse()
+                .map_err(|_| bad_request("invalid_user_id", "Invalid id"))?,
+            None => return Ok(None),
+        };
+
+        let user = self
+            .client
+            .query_authentication_request(id)
+            .await
+            .to_err()?;
+        users
+            .remember(self.name(), user.id, &user.display_name)
+            .to_err()?;
+
+        Ok(Some(SessionUser {
+            id: user.id,
+            name: user.display_name,
+            guest: false,
+            scopes: None,
+        }))
+    }
+
+    fn profile_picture_url(&self, _users: &Users, user_id: u32) -> Option<String> {
+        Some(format!(
+            "https://{}/api/profiles/{user_id}/picture",
+            self.client.host()
+        ))
+    }
+}
@@ -0,0 +1,108 @@
+use {
+    super::AuthProvider,
+    crate::{
+        config::AuthConfig,
+        error::{bad_request, not_found},
+        user::SessionUser,
+        users::Users,
+        AppData,
+    },
+    actix_session::Session,
+    actix_web::{get, web, Error, HttpResponse, Responder},
+    async_trait::async_trait,
+    std::collections::HashMap,
+};
+
+/// Users offered by the picker, anyone else can still be logged in as with `?as=<id>&name=<name>`.
+const USERS: [(u32, &str); 3] = [(1, "Alice"), (2, "Bob"), (3, "Carol")];
+
+/// Logs in as any user without checking anything, for local development and integration tests.
+pub struct DevProvider;
+
+impl DevProvider {
+    pub const NAME: &'static str = "dev";
+
+    /// Returns `None` unless `auth.dev` is set, release builds also need `auth.dev_allow_release`.
+    pub fn from_config(config: &AuthConfig) -> Option<Self> {
+        if !config.dev || (cfg!(not(debug_assertions)) && !config.dev_allow_release) {
+            return None;
+        }
+        tracing::warn!("Development authentication is enabled, anyone can log in as any user");
+        Some(Self)
+    }
+}
+
+#[async_trait(?Send)]
+impl AuthProvider for DevProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn display_name(&self) -> &str {
+        "Development Login"
+    }
+
+    async fn login_url(&self, _session: &Session) -> Result<String, Error> {
+        Ok(String::from("/auth/dev"))
+    }
+
+    async fn callback(
+        &self,
+        _users: &Users,
+        query: &HashMap<String, String>,
+        _session: &Session,
+    ) -> Result<Option<SessionUser>, Error> {
+        let Some(id) = query.get("as") else {
+            return Ok(None);
+        };
+        let id = id
+            .parse()
+            .map_err(|_| bad_request("invalid_user_id", format!("Invalid user id {id:?}")))?;
+        let name = match query.get("name") {
+            Some(name) if !name.trim().is_empty() => name.trim().to_owned(),
+            _ => format!("User {id}"),
+        };
+        Ok(Some(SessionUser {
+            id,
+            name,
+            guest: false,
+            scopes: None,
+        }))
+    }
+
+    fn profile_picture_url(&self, _users: &Users, _user_id: u32) -> Option<String> {
+        None
+    }
+}
+
+#[get("/auth/dev")]
+async fn picker(data: web::Data<AppData>) -> Result<impl Responder, Error> {
+    if data.auth_providers.get(DevProvider::NAME).is_none() {
+        return Err(not_found(
+            "dev_login_disabled",
+            "Development login is disabled",
+        ));
+    }
+
+    let callback = format!("/auth/providers/{}/callback", DevProvider::NAME);
+    let users: String = USERS
+        .iter()
+        .map(|(id, name)| {
+            format!("<li><a href=\"{callback}?as={id}&name={name}\">{name} ({id})</a></li>")
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html><html><head><title>Development Login</title></head><body>\
+            <h1>Development Login</h1><ul>{users}</ul>\
+            <form action=\"{callback}\">\
+            <input name=\"as\" type=\"number\" min=\"0\" placeholder=\"Id\" required> \
+            <input name=\"name\" placeholder=\"Name\"> \
+            <button>Login</button></form></body></html>"
+        )))
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(picker);
+}
@@ -0,0 +1,472 @@
+use {
+    super::AuthProvider,
+    crate::{
+        config::{Config, LocalConfig},
+        error::{
+            bad_request, conflict, forbidden, internal, not_found, too_many_requests, unauthorized,
+            ToErr,
+        },
+        storage,
+        user::{valid_name, SessionUser},
+        users::Users,
+        AppData,
+    },
+    actix_session::Session,
+    actix_web::{get, post, web, Error, HttpRequest, HttpResponse, Responder},
+    argon2::{
+        password_hash::{
+            rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+        },
+        Argon2,
+    },
+    async_trait::async_trait,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt,
+        net::{IpAddr, Ipv4Addr},
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    },
+};
+
+const FILE: &str = "accounts.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Registration {
+    /// Anyone can create an account.
+    Open,
+    /// Accounts can only be created by an administrator.
+    Closed,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Account {
+    username: String,
+    name: String,
+    password_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AccountsFile {
+    accounts: Vec<Account>,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidUsername,
+    InvalidName,
+    InvalidPassword,
+    UsernameTaken,
+    InvalidCredentials,
+    NotFound,
+    Hash(argon2::password_hash::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUsername => write!(
+                f,
+                "Usernames must be 3 to 32 characters of a-z, 0-9, '_', '.' or '-'"
+            ),
+            Self::InvalidName => write!(
+                f,
+                "Display names must be 1 to 32 characters long, without control characters"
+            ),
+            Self::InvalidPassword => write!(f, "Passwords must be at least 8 characters long"),
+            Self::UsernameTaken => write!(f, "Username already taken"),
+            Self::InvalidCredentials => write!(f, "Invalid username or password"),
+            Self::NotFound => write!(f, "Account not found"),
+            Self::Hash(err) => write!(f, "Unable to hash password: {err}"),
+            Self::Io(err) => write!(f, "Unable to access accounts: {err}"),
+        }
+    }
+}
+
+impl ToErr for AccountError {
+    type Return = Error;
+
+    fn to_err(self) -> Self::Return {
+        match self {
+            Self::InvalidUsername => bad_request("invalid_username", self.to_string()),
+            Self::InvalidName => bad_request("invalid_name", self.to_string()),
+            Self::InvalidPassword => bad_request("invalid_password", self.to_string()),
+            Self::UsernameTaken => conflict("username_taken", self.to_string()),
+            Self::InvalidCredentials => unauthorized("invalid_credentials", self.to_string()),
+            Self::NotFound => not_found("account_not_found", self.to_string()),
+            Self::Hash(_) => internal("internal", self.to_string()),
+            Self::Io(err) => err.to_err(),
+        }
+    }
+}
+
+/// Username and password accounts stored by the server itself.
+pub struct LocalAccounts {
+    registration: Registration,
+    path: PathBuf,
+    /// Held while the file is read and written, it's reloaded every time
+    /// since `add-user` may change it while the server is running.
+    lock: Mutex<()>,
+}
+
+fn hash_password(password: &str) -> Result<String, AccountError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(AccountError::Hash)
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+fn validate_username(username: &str) -> Result<(), AccountError> {
+    let valid = (3..=32).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_.-".contains(c));
+    match valid {
+        true => Ok(()),
+        false => Err(AccountError::InvalidUsername),
+    }
+}
+
+fn validate_password(password: &str) -> Result<(), AccountError> {
+    match password.chars().count() >= 8 {
+        true => Ok(()),
+        false => Err(AccountError::InvalidPassword),
+    }
+}
+
+impl LocalAccounts {
+    fn load(&self) -> Result<AccountsFile, AccountError> {
+        storage::read(&self.path).map_err(AccountError::Io)
+    }
+
+    /// Returns `None` unless local accounts are enabled.
+    pub fn from_config(config: &LocalConfig, data_dir: &Path) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let path = data_dir.join(FILE);
+        Some(Self {
+            registration: config.registration,
+            lock: Mutex::new(()),
+            path,
+        })
+    }
+
+    /// Creates an account, ignoring the registration setting.
+    /// Hashing is slow, so this should be called from a blocking context.
+    pub fn create(&self, username: &str, name: &str, password: &str) -> Result<(), AccountError> {
+        validate_username(username)?;
+        validate_password(password)?;
+        let name = match name.trim() {
+            "" => username,
+            name => name,
+        };
+        if !valid_name(name) {
+            return Err(AccountError::InvalidName);
+        }
+        let password_hash = hash_password(password)?;
+
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.load()?;
+        if file.accounts.iter().any(|x| x.username == username) {
+            return Err(AccountError::UsernameTaken);
+        }
+        file.accounts.push(Account {
+            username: username.to_owned(),
+            name: name.to_owned(),
+            password_hash,
+        });
+        storage::save(&self.path, &file).map_err(AccountError::Io)
+    }
+
+    /// Checks a username and password, returning the account's display name if they match.
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<String, AccountError> {
+        let account = {
+            let _guard = self.lock.lock().unwrap();
+            self.load()?
+                .accounts
+                .into_iter()
+                .find(|x| x.username == username)
+        };
+        match account {
+            Some(account) if verify_password(password, &account.password_hash) => Ok(account.name),
+            Some(_) => Err(AccountError::InvalidCredentials),
+            None => {
+                // hash anyway, so unknown usernames take as long as wrong passwords
+                let _ = hash_password(password);
+                Err(AccountError::InvalidCredentials)
+            }
+        }
+    }
+
+    pub fn change_password(
+        &self,
+        username: &str,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<(), AccountError> {
+        validate_password(new_password)?;
+        self.authenticate(username, current_password)?;
+        let password_hash = hash_password(new_password)?;
+
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.load()?;
+        let account = file
+            .accounts
+            .iter_mut()
+            .find(|x| x.username == username)
+            .ok_or(AccountError::NotFound)?;
+        account.password_hash = password_hash;
+        storage::save(&self.path, &file).map_err(AccountError::Io)
+    }
+}
+
+/// `sync-play add-user <username> [display name]`, reads the password from stdin.
+pub fn add_user_command(
+    config: &Config,
+    username: &str,
+    name: Option<&str>,
+) -> std::io::Result<()> {
+    let name = name.unwrap_or_default();
+    let Some(accounts) = LocalAccounts::from_config(&config.auth.local, &config.data_dir) else {
+        eprintln!("Local accounts are disabled, set auth.local.enabled or LOCAL_ACCOUNTS=true");
+        std::process::exit(1);
+    };
+
+    eprint!("Password: ");
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    match accounts.create(username, name, password.trim_end_matches(['\r', '\n'])) {
+        Ok(()) => {
+            eprintln!("Created user {username:?}");
+            Ok(())
+        }
+        Err(err) => {
+            eprintln!("Unable to create user: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn resolve_user(users: &Users, username: &str, name: String) -> Result<SessionUser, Error> {
+    let user = users
+        .resolve(LocalProvider::NAME, username, name, None)
+        .to_err()?;
+    Ok(SessionUser {
+        id: user.id,
+        name: user.name,
+        guest: false,
+        scopes: None,
+    })
+}
+
+/// Hashing passwords is slow, so each client address only gets a few attempts.
+fn check_rate_limit(data: &AppData, req: &HttpRequest) -> Result<(), Error> {
+    let ip = req
+        .peer_addr()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+    match data.login_limiter.try_take(ip) {
+        true => Ok(()),
+        false => Err(too_many_requests(
+            "rate_limited",
+            "Too many login attempts, try again later",
+        )),
+    }
+}
+
+fn accounts(data: &AppData) -> Result<Arc<LocalAccounts>, Error> {
+    data.local_accounts
+        .clone()
+        .ok_or_else(|| not_found("local_accounts_disabled", "Local accounts are disabled"))
+}
+
+/// Logs in through the form on the login page rather than a redirect.
+pub struct LocalProvider;
+
+impl LocalProvider {
+    pub const NAME: &'static str = "local";
+}
+
+#[async_trait(?Send)]
+impl AuthProvider for LocalProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn display_name(&self) -> &str {
+        "Username and Password"
+    }
+
+    async fn login_url(&self, _session: &Session) -> Result<String, Error> {
+        Ok(String::from("/login"))
+    }
+
+    async fn callback(
+        &self,
+        _users: &Users,
+        _query: &HashMap<String, String>,
+        _session: &Session,
+    ) -> Result<Option<SessionUser>, Error> {
+        Ok(None)
+    }
+
+    fn profile_picture_url(&self, _users: &Users, _user_id: u32) -> Option<String> {
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct LocalSettings {
+    registration: Registration,
+}
+
+#[get("/auth/local")]
+async fn settings(data: web::Data<AppData>) -> Result<impl Responder, Error> {
+    Ok(web::Json(LocalSettings {
+        registration: accounts(&data)?.registration,
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Register {
+    username: String,
+    password: String,
+    #[serde(default)]
+    name: String,
+}
+
+#[post("/auth/local/register")]
+async fn register(
+    data: web::Data<AppData>,
+    req: HttpRequest,
+    session: Session,
+    body: web::Json<Register>,
+) -> Result<impl Responder, Error> {
+    let accounts = accounts(&data)?;
+    if accounts.registration != Registration::Open {
+        return Err(forbidden(
+            "registration_disabled",
+            "Registration is disabled",
+        ));
+    }
+    check_rate_limit(&data, &req)?;
+
+    let body = web::block(move || {
+        accounts
+            .create(&body.username, &body.name, &body.password)
+            .map(|_| body)
+    })
+    .await?
+    .to_err()?;
+    let name = match body.name.trim() {
+        "" => body.username.clone(),
+        name => name.to_owned(),
+    };
+    let user = resolve_user(&data.users, &body.username, name)?;
+    session.renew();
+    session.insert("user", &user).to_err()?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+#[post("/auth/local/login")]
+async fn login(
+    data: web::Data<AppData>,
+    req: HttpRequest,
+    session: Session,
+    body: web::Json<Login>,
+) -> Result<impl Responder, Error> {
+    let accounts = accounts(&data)?;
+    check_rate_limit(&data, &req)?;
+    let username = body.username.clone();
+    let name = web::block(move || accounts.authenticate(&body.username, &body.password))
+        .await?
+        .to_err()?;
+    let user = resolve_user(&data.users, &username, name)?;
+    session.renew();
+    session.insert("user", &user).to_err()?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePassword {
+    current_password: String,
+    new_password: String,
+}
+
+#[post("/auth/local/password")]
+async fn change_password(
+    data: web::Data<AppData>,
+    req: HttpRequest,
+    session: Session,
+    body: web::Json<ChangePassword>,
+) -> Result<impl Responder, Error> {
+    let user = SessionUser::from_session(&session, &data)?;
+    let accounts = accounts(&data)?;
+    check_rate_limit(&data, &req)?;
+    let account = data
+        .users
+        .get(user.id)
+        .filter(|x| x.provider == LocalProvider::NAME)
+        .ok_or_else(|| bad_request("not_local_account", "Not logged in with a local account"))?;
+
+    web::block(move || {
+        accounts.change_password(&account.subject, &body.current_password, &body.new_password)
+    })
+    .await?
+    .to_err()?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(settings);
+    cfg.service(register);
+    cfg.service(login);
+    cfg.service(change_password);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_display_names() {
+        let dir = std::env::temp_dir().join(format!("sync-play-local-{}", std::process::id()));
+        let config = LocalConfig {
+            enabled: true,
+            registration: Registration::Open,
+        };
+        let accounts = LocalAccounts::from_config(&config, &dir).unwrap();
+        for name in ["a".repeat(33).as_str(), "new\nline", "bell\u{7}"] {
+            assert!(
+                matches!(
+                    accounts.create("alice", name, "password"),
+                    Err(AccountError::InvalidName)
+                ),
+                "{name:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,771 @@
+use {
+    crate::{
+        auth::local::Registration,
+        rate_limit::RateLimits,
+        session_key,
+        sessions::StoreKind,
+        telemetry::{self, LogFormat},
+    },
+    clap::{Parser, Subcommand},
+    serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer},
+    std::{
+        fmt,
+        path::{Path, PathBuf},
+        str::FromStr,
+    },
+};
+
+const DEFAULT_FILE: &str = "sync-play.toml";
+
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    after_help = "Everything but the port and data directory is set in the configuration file or environment, see sync-play.example.toml and .env.example."
+)]
+pub struct Cli {
+    /// TOML configuration file, `sync-play.toml` is used if it exists.
+    #[arg(long, short, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+    /// Port to listen on.
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Directory for everything the server persists.
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    /// Print the effective configuration, with secrets redacted, and exit.
+    #[arg(long)]
+    pub print_config: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create a local account, the password is read from stdin.
+    AddUser {
+        username: String,
+        display_name: Option<String>,
+    },
+    /// Replace the session key, the previous one stays valid for `session.key_grace` seconds.
+    RotateSessionKey,
+    /// Exit with an error unless the server on `port` responds to `/healthz`.
+    HealthCheck,
+}
+
+/// A value that is never printed.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_owned()))
+    }
+}
+
+/// Everything the server can be configured with.
+///
+/// Values are taken from, in increasing precedence: the defaults, the TOML file,
+/// environment variables (also read from `.env`) and the `--port` and `--data-dir` flags.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `PORT`
+    pub port: u16,
+    /// `DATA_DIR`
+    pub data_dir: PathBuf,
+    /// Users who are always admins, `ADMIN_USERS` as a comma separated list.
+    pub admin_users: Vec<u32>,
+    /// How often the latest `UpdateTime` of each room is broadcast, `TIME_UPDATE_TICK_MS`.
+    pub time_update_tick_ms: u64,
+    pub session: SessionConfig,
+    pub auth: AuthConfig,
+    pub media: MediaConfig,
+    pub pictures: PicturesConfig,
+    pub rate_limits: RateLimits,
+    pub shutdown: ShutdownConfig,
+    pub metrics: MetricsConfig,
+    pub log: LogConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 80,
+            data_dir: PathBuf::from("data"),
+            admin_users: Vec::new(),
+            time_update_tick_ms: 500,
+            session: SessionConfig::default(),
+            auth: AuthConfig::default(),
+            media: MediaConfig::default(),
+            pictures: PicturesConfig::default(),
+            rate_limits: RateLimits::default(),
+            shutdown: ShutdownConfig::default(),
+            metrics: MetricsConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// `SESSION_STORE`
+    pub store: StoreKind,
+    /// Base64 encoded, at least 64 bytes, `SESSION_KEY`. Overrides the key file.
+    pub key: Option<Secret>,
+    /// Still accepted and re-signed with `key`, `SESSION_KEY_PREVIOUS`.
+    pub key_previous: Option<Secret>,
+    /// Unix time until which `key_previous` is accepted, `SESSION_KEY_PREVIOUS_EXPIRES`.
+    pub key_previous_expires: Option<u64>,
+    /// `SESSION_KEY_FILE`, defaults to `session_key.json` in the data directory.
+    pub key_file: Option<PathBuf>,
+    /// Seconds the previous key stays valid after `rotate-session-key`, `SESSION_KEY_GRACE`.
+    pub key_grace: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            store: StoreKind::File,
+            key: None,
+            key_previous: None,
+            key_previous_expires: None,
+            key_file: None,
+            key_grace: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// `DEV_AUTH`
+    pub dev: bool,
+    /// `DEV_AUTH_ALLOW_RELEASE`
+    pub dev_allow_release: bool,
+    pub local: LocalConfig,
+    pub auth_server: AuthServerConfig,
+    pub oidc: OidcConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalConfig {
+    /// `LOCAL_ACCOUNTS`
+    pub enabled: bool,
+    /// `LOCAL_REGISTRATION`
+    pub registration: Registration,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            registration: Registration::Closed,
+        }
+    }
+}
+
+/// Enabled by setting `host`.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthServerConfig {
+    /// `AUTH_SERVER_HOST`
+    pub host: Option<String>,
+    /// `AUTH_SERVER_ID`
+    pub id: Option<u64>,
+    /// `AUTH_SERVER_KEY`
+    pub key: PathBuf,
+    /// `PRIVATE_KEY`
+    pub private_key: PathBuf,
+}
+
+impl Default for AuthServerConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            id: None,
+            key: PathBuf::from("auth_server.pem"),
+            private_key: PathBuf::from("private.pem"),
+        }
+    }
+}
+
+/// Enabled by setting `issuer`.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    /// `OIDC_ISSUER`
+    pub issuer: Option<String>,
+    /// `OIDC_CLIENT_ID`
+    pub client_id: Option<String>,
+    /// `OIDC_CLIENT_SECRET`
+    pub client_secret: Option<Secret>,
+    /// `OIDC_REDIRECT_URL`
+    pub redirect_url: Option<String>,
+    /// `OIDC_SCOPES`
+    pub scopes: String,
+    /// `OIDC_DISPLAY_NAME`
+    pub display_name: String,
+    /// Members are made admins, and lose the role when they leave the group, `OIDC_ADMIN_GROUP`.
+    /// Admin roles set through the admin API are never taken away.
+    pub admin_group: Option<String>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            issuer: None,
+            client_id: None,
+            client_secret: None,
+            redirect_url: None,
+            scopes: String::from("openid profile email"),
+            display_name: String::from("Single Sign-On"),
+            admin_group: None,
+        }
+    }
+}
+
+/// Enabled by setting `dir`.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MediaConfig {
+    /// `MEDIA_DIR`
+    pub dir: Option<PathBuf>,
+    /// Random on every start if unset, `MEDIA_URL_SECRET`.
+    pub url_secret: Option<Secret>,
+    /// Seconds signed media urls stay valid, `MEDIA_URL_TTL`.
+    pub url_ttl: u64,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            url_secret: None,
+            url_ttl: 6 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PicturesConfig {
+    /// Seconds, `PICTURE_CACHE_TTL`.
+    pub cache_ttl: u64,
+}
+
+impl Default for PicturesConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// Seconds to notify rooms and finish requests before exiting anyway, `SHUTDOWN_DEADLINE`.
+    pub deadline: u64,
+    /// Seconds until the server is expected back, announced to rooms, `SHUTDOWN_RESTART_DELAY`.
+    pub restart_delay: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            deadline: 30,
+            restart_delay: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Serve `/metrics`, `METRICS`. Off by default, it shows request and login timings.
+    pub enabled: bool,
+    /// Required as a bearer token to scrape `/metrics` if set, `METRICS_TOKEN`.
+    pub token: Option<Secret>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Filter directives like `info,sync_play=debug`, `RUST_LOG`. Admins can change it at runtime.
+    pub level: String,
+    /// `LOG_FORMAT`
+    pub format: LogFormat,
+    /// OpenTelemetry collector to export traces to, `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    /// Needs the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from(match cfg!(debug_assertions) {
+                true => "debug",
+                false => "info",
+            }),
+            format: LogFormat::Text,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+/// Expands `~` in paths from the configuration.
+pub fn expand(path: &Path) -> PathBuf {
+    PathBuf::from(&*shellexpand::tilde(&path.to_string_lossy()))
+}
+
+/// Overlays environment variables, collecting every invalid one.
+struct Env<'a> {
+    errors: &'a mut Vec<String>,
+    /// Looks up a variable, `std::env::var` outside of tests.
+    var: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl Env<'_> {
+    fn parse<T: FromStr>(&mut self, name: &str, target: &mut T)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = (self.var)(name) {
+            match value.trim().parse() {
+                Ok(value) => *target = value,
+                Err(err) => self.errors.push(format!("{name}: {err}")),
+            }
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    where
+        T::Err: fmt::Display,
+    {
+        if let Some(value) = (self.var)(name) {
+            match value.trim().parse() {
+                Ok(value) => *target = Some(value),
+                Err(err) => self.errors.push(format!("{name}: {err}")),
+            }
+        }
+    }
+
+    /// For enums, which are spelled the same as in the TOML file.
+    fn variant<T: DeserializeOwned>(&mut self, name: &str, target: &mut T) {
+        if let Some(value) = (self.var)(name) {
+            let deserializer =
+                serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value.trim());
+            match T::deserialize(deserializer) {
+                Ok(value) => *target = value,
+                Err(err) => self.errors.push(format!("{name}: {err}")),
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Loads and validates the configuration, returning every problem found.
+    pub fn load(cli: &Cli) -> Result<Self, Vec<String>> {
+        Self::load_with_env(cli, &|name| std::env::var(name).ok())
+    }
+
+    /// Like [`Self::load`], with environment variables looked up by `var`.
+    fn load_with_env(cli: &Cli, var: &dyn Fn(&str) -> Option<String>) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let path = cli.config.clone().or_else(|| {
+            Path::new(DEFAULT_FILE)
+                .is_file()
+                .then(|| PathBuf::from(DEFAULT_FILE))
+        });
+        let mut config = match path {
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(text) => toml::from_str(&text).unwrap_or_else(|err| {
+                    errors.push(format!("{}: {err}", path.display()));
+                    Self::default()
+                }),
+                Err(err) => {
+                    errors.push(format!("{}: {err}", path.display()));
+                    Self::default()
+                }
+            },
+            None => Self::default(),
+        };
+
+        config.apply_env(&mut Env {
+            errors: &mut errors,
+            var,
+        });
+        if let Some(port) = cli.port {
+            config.port = port;
+        }
+        if let Some(data_dir) = &cli.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+        config.data_dir = expand(&config.data_dir);
+
+        config.validate(&mut errors);
+        match errors.is_empty() {
+            true => Ok(config),
+            false => Err(errors),
+        }
+    }
+
+    fn apply_env(&mut self, env: &mut Env) {
+        env.parse("PORT", &mut self.port);
+        env.parse("DATA_DIR", &mut self.data_dir);
+        if let Some(value) = (env.var)("ADMIN_USERS") {
+            match value
+                .split(',')
+                .filter(|id| !id.trim().is_empty())
+                .map(|id| id.trim().parse())
+                .collect()
+            {
+                Ok(admins) => self.admin_users = admins,
+                Err(err) => env.errors.push(format!("ADMIN_USERS: {err}")),
+            }
+        }
+        env.parse("TIME_UPDATE_TICK_MS", &mut self.time_update_tick_ms);
+
+        let session = &mut self.session;
+        env.variant("SESSION_STORE", &mut session.store);
+        env.optional("SESSION_KEY", &mut session.key);
+        env.optional("SESSION_KEY_PREVIOUS", &mut session.key_previous);
+        env.optional(
+            "SESSION_KEY_PREVIOUS_EXPIRES",
+            &mut session.key_previous_expires,
+        );
+        env.optional("SESSION_KEY_FILE", &mut session.key_file);
+        env.parse("SESSION_KEY_GRACE", &mut session.key_grace);
+
+        let auth = &mut self.auth;
+        env.parse("DEV_AUTH", &mut auth.dev);
+        env.parse("DEV_AUTH_ALLOW_RELEASE", &mut auth.dev_allow_release);
+        env.parse("LOCAL_ACCOUNTS", &mut auth.local.enabled);
+        env.variant("LOCAL_REGISTRATION", &mut auth.local.registration);
+        env.optional("AUTH_SERVER_HOST", &mut auth.auth_server.host);
+        env.optional("AUTH_SERVER_ID", &mut auth.auth_server.id);
+        env.parse("AUTH_SERVER_KEY", &mut auth.auth_server.key);
+        env.parse("PRIVATE_KEY", &mut auth.auth_server.private_key);
+        env.optional("OIDC_ISSUER", &mut auth.oidc.issuer);
+        env.optional("OIDC_CLIENT_ID", &mut auth.oidc.client_id);
+        env.optional("OIDC_CLIENT_SECRET", &mut auth.oidc.client_secret);
+        env.optional("OIDC_REDIRECT_URL", &mut auth.oidc.redirect_url);
+        env.parse("OIDC_SCOPES", &mut auth.oidc.scopes);
+        env.parse("OIDC_DISPLAY_NAME", &mut auth.oidc.display_name);
+        env.optional("OIDC_ADMIN_GROUP", &mut auth.oidc.admin_group);
+
+        env.optional("MEDIA_DIR", &mut self.media.dir);
+        env.optional("MEDIA_URL_SECRET", &mut self.media.url_secret);
+        env.parse("MEDIA_URL_TTL", &mut self.media.url_ttl);
+        env.parse("PICTURE_CACHE_TTL", &mut self.pictures.cache_ttl);
+        env.parse("SHUTDOWN_DEADLINE", &mut self.shutdown.deadline);
+        env.parse("SHUTDOWN_RESTART_DELAY", &mut self.shutdown.restart_delay);
+        env.parse("METRICS", &mut self.metrics.enabled);
+        env.optional("METRICS_TOKEN", &mut self.metrics.token);
+        env.parse("RUST_LOG", &mut self.log.level);
+        env.variant("LOG_FORMAT", &mut self.log.format);
+        env.optional("OTEL_EXPORTER_OTLP_ENDPOINT", &mut self.log.otlp_endpoint);
+
+        let limits = &mut self.rate_limits;
+        env.parse("RATE_LIMIT_PLAYBACK", &mut limits.playback);
+        env.parse("RATE_LIMIT_UPDATE_TIME", &mut limits.update_time);
+        env.parse("RATE_LIMIT_PING", &mut limits.ping);
+        env.parse("RATE_LIMIT_INVALID", &mut limits.invalid);
+        env.parse("RATE_LIMIT_ROOM_CREATION", &mut limits.room_creation);
+        env.parse("RATE_LIMIT_SUBTITLE_UPLOAD", &mut limits.subtitle_upload);
+        env.parse("RATE_LIMIT_LOGIN", &mut limits.login);
+        env.parse("RATE_LIMIT_MAX_VIOLATIONS", &mut limits.max_violations);
+        env.parse("WS_MAX_FRAME_SIZE", &mut limits.max_frame_size);
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let auth = &self.auth;
+        if !auth.dev
+            && !auth.local.enabled
+            && auth.auth_server.host.is_none()
+            && auth.oidc.issuer.is_none()
+        {
+            errors.push(String::from(
+                "No authentication provider configured, set auth.auth_server.host, auth.oidc.issuer, auth.local.enabled or auth.dev",
+            ));
+        }
+        if auth.dev && cfg!(not(debug_assertions)) && !auth.dev_allow_release {
+            errors.push(String::from(
+                "auth.dev is only available in debug builds, set auth.dev_allow_release to override",
+            ));
+        }
+        if auth.auth_server.host.is_some() {
+            if auth.auth_server.id.is_none() {
+                errors.push(String::from("auth.auth_server.id is required"));
+            }
+            for path in [&auth.auth_server.key, &auth.auth_server.private_key] {
+                if !expand(path).is_file() {
+                    errors.push(format!("Unable to open {path:?}"));
+                }
+            }
+        }
+        if auth.oidc.issuer.is_some() {
+            if auth.oidc.client_id.is_none() {
+                errors.push(String::from("auth.oidc.client_id is required"));
+            }
+            if auth.oidc.redirect_url.is_none() {
+                errors.push(String::from("auth.oidc.redirect_url is required"));
+            }
+        }
+
+        for (name, key) in [
+            ("session.key", &self.session.key),
+            ("session.key_previous", &self.session.key_previous),
+        ] {
+            if let Some(Err(err)) = key.as_ref().map(|key| session_key::decode(key.expose())) {
+                errors.push(format!("{name}: {err}"));
+            }
+        }
+        if self.session.key_previous.is_some() && self.session.key_previous_expires.is_none() {
+            errors.push(String::from(
+                "session.key_previous_expires is required with session.key_previous",
+            ));
+        }
+        if let Some(dir) = &self.media.dir {
+            if !expand(dir).is_dir() {
+                errors.push(format!("Unable to open media directory {dir:?}"));
+            }
+        }
+        if self.time_update_tick_ms == 0 {
+            errors.push(String::from("time_update_tick_ms must be at least 1"));
+        }
+        if self.rate_limits.max_frame_size == 0 {
+            errors.push(String::from(
+                "rate_limits.ws_max_frame_size must be at least 1",
+            ));
+        }
+        if let Err(err) = telemetry::parse_filter(&self.log.level) {
+            errors.push(format!("log.level: {err}"));
+        }
+        if self.log.otlp_endpoint.is_some() && !cfg!(feature = "otlp") {
+            errors.push(String::from(
+                "log.otlp_endpoint: sync-play was built without the otlp feature",
+            ));
+        }
+        if self.shutdown.deadline == 0 {
+            errors.push(String::from("shutdown.deadline must be at least 1"));
+        }
+    }
+
+    /// The configuration as TOML, with secrets redacted.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("Unable to serialize configuration")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        base64::{engine::general_purpose::STANDARD, Engine},
+        std::collections::HashMap,
+    };
+
+    fn config_file(name: &str, text: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sync-play-config-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sync-play.toml");
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Config, Vec<String>> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let cli = Cli::parse_from([&"sync-play"].into_iter().chain(args));
+        Config::load_with_env(&cli, &|name| env.get(name).cloned())
+    }
+
+    fn errors(config: &Config) -> Vec<String> {
+        let mut errors = Vec::new();
+        config.validate(&mut errors);
+        errors
+    }
+
+    fn dev_config() -> Config {
+        let mut config = Config::default();
+        config.auth.dev = true;
+        config
+    }
+
+    #[test]
+    fn flags_override_env_which_overrides_the_file() {
+        let path = config_file(
+            "precedence",
+            r#"
+            port = 1000
+            data_dir = "file-data"
+            time_update_tick_ms = 250
+
+            [auth]
+            dev = true
+
+            [rate_limits]
+            playback = "1/1"
+            ping = "2/2"
+            "#,
+        );
+        let path = path.to_str().unwrap();
+
+        let config = load(&["--config", path], &[]).unwrap();
+        assert_eq!(config.port, 1000);
+        assert_eq!(config.data_dir, PathBuf::from("file-data"));
+
+        let env = [
+            ("PORT", "2000"),
+            ("DATA_DIR", "env-data"),
+            ("RATE_LIMIT_PLAYBACK", "3/3"),
+        ];
+        let config = load(&["--config", path], &env).unwrap();
+        assert_eq!(config.port, 2000);
+        assert_eq!(config.data_dir, PathBuf::from("env-data"));
+
+        let config = load(
+            &[
+                "--config",
+                path,
+                "--port",
+                "3000",
+                "--data-dir",
+                "flag-data",
+            ],
+            &env,
+        )
+        .unwrap();
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.data_dir, PathBuf::from("flag-data"));
+        assert_eq!(config.rate_limits.playback.to_string(), "3/3");
+        // only set in the file, or not at all
+        assert_eq!(config.time_update_tick_ms, 250);
+        assert_eq!(config.rate_limits.ping.to_string(), "2/2");
+        assert_eq!(config.shutdown.deadline, 30);
+    }
+
+    #[test]
+    fn collects_file_and_env_errors() {
+        let path = config_file("invalid", "port = \"eighty\"\n");
+        let Err(errors) = load(&["--config", path.to_str().unwrap()], &[]) else {
+            panic!("invalid file accepted");
+        };
+        assert!(
+            errors[0].starts_with(&path.display().to_string()),
+            "{errors:?}"
+        );
+
+        let env = [
+            ("DEV_AUTH", "true"),
+            ("PORT", "eighty"),
+            ("ADMIN_USERS", "1,two"),
+            ("SESSION_STORE", "disk"),
+            ("RATE_LIMIT_PING", "5"),
+        ];
+        let Err(errors) = load(&[], &env) else {
+            panic!("invalid environment accepted");
+        };
+        let names: Vec<&str> = errors
+            .iter()
+            .map(|error| error.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["PORT", "ADMIN_USERS", "SESSION_STORE", "RATE_LIMIT_PING"]
+        );
+    }
+
+    #[test]
+    fn accepts_a_minimal_configuration() {
+        assert!(errors(&dev_config()).is_empty());
+    }
+
+    #[test]
+    fn reports_each_invalid_setting() {
+        type Change = fn(&mut Config);
+        let cases: [(&str, Change); 12] = [
+            ("No authentication provider", |config| {
+                config.auth.dev = false
+            }),
+            ("auth.auth_server.id is required", |config| {
+                config.auth.auth_server.host = Some(String::from("auth.example.com"));
+                // any file that exists
+                config.auth.auth_server.key = PathBuf::from("Cargo.toml");
+                config.auth.auth_server.private_key = PathBuf::from("Cargo.toml");
+            }),
+            ("Unable to open \"missing.pem\"", |config| {
+                config.auth.auth_server.host = Some(String::from("auth.example.com"));
+                config.auth.auth_server.id = Some(1);
+                config.auth.auth_server.key = PathBuf::from("Cargo.toml");
+                config.auth.auth_server.private_key = PathBuf::from("missing.pem");
+            }),
+            ("auth.oidc.client_id is required", |config| {
+                config.auth.oidc.issuer = Some(String::from("https://sso.example.com"));
+                config.auth.oidc.redirect_url = Some(String::from("https://example.com/cb"));
+            }),
+            ("auth.oidc.redirect_url is required", |config| {
+                config.auth.oidc.issuer = Some(String::from("https://sso.example.com"));
+                config.auth.oidc.client_id = Some(String::from("sync-play"));
+            }),
+            ("session.key:", |config| {
+                config.session.key = Some(Secret(String::from("too short")))
+            }),
+            ("session.key_previous_expires is required", |config| {
+                config.session.key_previous = Some(Secret(STANDARD.encode([7; 64])))
+            }),
+            ("Unable to open media directory", |config| {
+                config.media.dir = Some(PathBuf::from("/nonexistent/sync-play-media"))
+            }),
+            ("time_update_tick_ms must be at least 1", |config| {
+                config.time_update_tick_ms = 0
+            }),
+            (
+                "rate_limits.ws_max_frame_size must be at least 1",
+                |config| config.rate_limits.max_frame_size = 0,
+            ),
+            ("log.level:", |config| {
+                config.log.level = String::from("sync_play=loud")
+            }),
+            ("shutdown.deadline must be at least 1", |config| {
+                config.shutdown.deadline = 0
+            }),
+        ];
+        for (expected, change) in cases {
+            let mut config = dev_config();
+            change(&mut config);
+            let errors = errors(&config);
+            assert_eq!(errors.len(), 1, "{expected}: {errors:?}");
+            assert!(errors[0].starts_with(expected), "{expected}: {errors:?}");
+        }
+    }
+}
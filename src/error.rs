@@ -1,8 +1,154 @@
 use {
-    actix_web::error::{Error, ErrorInternalServerError, ErrorNotFound},
+    crate::request_id::RequestId,
+    actix_web::{
+        body::EitherBody,
+        dev::ServiceResponse,
+        http::{header, StatusCode},
+        Error, HttpResponse, ResponseError,
+    },
+    serde::Serialize,
     std::fmt,
 };
 
+/// An error returned by the API, sent as an RFC 7807 `application/problem+json` document.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    /// Stable and machine-readable, like `room_not_found`, clients should match on this.
+    code: &'static str,
+    message: String,
+    details: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    code: &'a str,
+    request_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    /// Attaches structured information, like which fields were invalid.
+    pub fn with_details(mut self, details: impl Serialize) -> Self {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
+
+    #[cfg(test)]
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// For errors of extractors and middleware, which only have a status and a message.
+    fn from_status(status: StatusCode, message: String) -> Self {
+        let code = match status {
+            StatusCode::BAD_REQUEST => "bad_request",
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+            StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+            status if status.is_server_error() => "internal",
+            _ => "error",
+        };
+        // server errors of dependencies may contain details that aren't meant for clients
+        let message = match status.is_server_error() {
+            true => status.canonical_reason().unwrap_or("Error").to_owned(),
+            false => message,
+        };
+        Self::new(status, code, message)
+    }
+
+    fn response(&self, request_id: Option<&str>) -> HttpResponse {
+        let problem = Problem {
+            kind: "about:blank",
+            title: self.status.canonical_reason().unwrap_or("Error"),
+            status: self.status.as_u16(),
+            detail: &self.message,
+            code: self.code,
+            request_id,
+            details: self.details.as_ref(),
+        };
+        HttpResponse::build(self.status)
+            .insert_header((header::CONTENT_TYPE, PROBLEM_JSON))
+            .json(problem)
+    }
+}
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.response(None)
+    }
+}
+
+macro_rules! constructors {
+    ($($name:ident => $status:ident),* $(,)?) => {
+        $(
+            pub fn $name(code: &'static str, message: impl Into<String>) -> Error {
+                ApiError::new(StatusCode::$status, code, message).into()
+            }
+        )*
+    };
+}
+
+constructors! {
+    bad_request => BAD_REQUEST,
+    unauthorized => UNAUTHORIZED,
+    forbidden => FORBIDDEN,
+    not_found => NOT_FOUND,
+    conflict => CONFLICT,
+    too_many_requests => TOO_MANY_REQUESTS,
+    internal => INTERNAL_SERVER_ERROR,
+    bad_gateway => BAD_GATEWAY,
+    service_unavailable => SERVICE_UNAVAILABLE,
+}
+
+/// Makes every error response a problem document carrying the request id,
+/// including those of extractors and middleware that don't produce an [`ApiError`].
+pub fn problem_details<B>(res: ServiceResponse<B>) -> ServiceResponse<EitherBody<B>> {
+    let Some(err) = res.response().error() else {
+        return res.map_into_left_body();
+    };
+    let request_id = RequestId::of(res.request());
+    let problem = match err.as_error::<ApiError>() {
+        Some(err) => err.response(request_id.as_deref()),
+        None => {
+            ApiError::from_status(res.status(), err.to_string()).response(request_id.as_deref())
+        }
+    };
+    res.into_response(problem).map_into_right_body()
+}
+
 pub trait ToErr {
     type Return;
     fn to_err(self) -> Self::Return;
@@ -27,18 +173,18 @@ impl<T, E: ToErr<Return = Error>> MapToErr for Result<Option<T>, E> {
     fn map_to_err(self) -> Self::Return {
         match self {
             Ok(Some(x)) => Ok(x),
-            Ok(None) => Err(ErrorNotFound("Not Found")),
+            Ok(None) => Err(not_found("not_found", "Not Found")),
             Err(err) => Err(err.to_err()),
         }
     }
 }
 
 fn internal_server_error<T: fmt::Debug>(err: T) -> Error {
-    log::error!("{err:?}");
+    tracing::error!(error = ?err, "Internal server error");
     #[cfg(debug_assertions)]
-    return ErrorInternalServerError(format!("Internal Server Error: {err:?}"));
+    return internal("internal", format!("Internal Server Error: {err:?}"));
     #[cfg(not(debug_assertions))]
-    return ErrorInternalServerError("Internal Server Error");
+    return internal("internal", "Internal Server Error");
 }
 
 impl ToErr for authentication_service::client::Error {
@@ -56,3 +202,19 @@ impl ToErr for actix_session::SessionInsertError {
         internal_server_error(self)
     }
 }
+
+impl ToErr for std::io::Error {
+    type Return = Error;
+
+    fn to_err(self) -> Self::Return {
+        internal_server_error(self)
+    }
+}
+
+impl ToErr for reqwest::Error {
+    type Return = Error;
+
+    fn to_err(self) -> Self::Return {
+        internal_server_error(self)
+    }
+}
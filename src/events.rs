@@ -0,0 +1,138 @@
+use {crate::clock::timestamp, serde::Serialize};
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum EventKind {
+    #[serde(rename_all = "camelCase")]
+    Created {
+        name: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Join {
+        ws_id: u32,
+        name: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Leave {
+        ws_id: u32,
+    },
+    Play {
+        time: Option<f64>,
+    },
+    Pause,
+    Seek {
+        time: Option<f64>,
+    },
+    MediaChanged {
+        path: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    SubtitleAdded {
+        track_id: u32,
+        label: String,
+    },
+    SubtitleOffset {
+        offset: i64,
+    },
+    GuestAccess {
+        allowed: bool,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomEvent {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub user_id: Option<u32>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Append-only log of everything that happened in a room.
+#[derive(Default, Clone)]
+pub struct EventLog {
+    events: Vec<RoomEvent>,
+}
+
+impl EventLog {
+    pub fn push(&mut self, user_id: Option<u32>, kind: EventKind) {
+        self.events.push(RoomEvent {
+            seq: self.events.len() as u64 + 1,
+            timestamp: timestamp(),
+            user_id,
+            kind,
+        });
+    }
+
+    /// Returns up to `limit` events with a sequence number greater than `after`.
+    pub fn page(&self, after: u64, limit: usize) -> &[RoomEvent] {
+        // sequence numbers start at 1 and are contiguous
+        let start = (after as usize).min(self.events.len());
+        let end = start.saturating_add(limit).min(self.events.len());
+        &self.events[start..end]
+    }
+
+    pub fn to_json_lines(&self) -> String {
+        let mut output = String::new();
+        for event in &self.events {
+            output.push_str(&serde_json::to_string(event).unwrap());
+            output.push('\n');
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(count: u32) -> EventLog {
+        let mut log = EventLog::default();
+        for ws_id in 0..count {
+            log.push(Some(1), EventKind::Leave { ws_id });
+        }
+        log
+    }
+
+    fn seqs(events: &[RoomEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.seq).collect()
+    }
+
+    #[test]
+    fn first_page() {
+        assert_eq!(seqs(log(5).page(0, 2)), [1, 2]);
+    }
+
+    #[test]
+    fn middle_page() {
+        assert_eq!(seqs(log(5).page(2, 2)), [3, 4]);
+    }
+
+    #[test]
+    fn last_page_is_partial() {
+        assert_eq!(seqs(log(5).page(4, 2)), [5]);
+        assert_eq!(seqs(log(5).page(0, usize::MAX)), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn out_of_range_pages_are_empty() {
+        assert!(log(5).page(5, 2).is_empty());
+        assert!(log(5).page(u64::MAX, 2).is_empty());
+        assert!(log(5).page(0, 0).is_empty());
+        assert!(log(0).page(0, 2).is_empty());
+    }
+
+    #[test]
+    fn json_lines() {
+        let lines = log(2).to_json_lines();
+        let lines: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["seq"], 2);
+        assert_eq!(lines[1]["type"], "leave");
+        assert_eq!(lines[1]["wsId"], 1);
+    }
+}
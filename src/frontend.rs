@@ -1,5 +1,5 @@
 use {
-    crate::user::SessionUser,
+    crate::{redirect, user::SessionUser, AppData},
     actix_session::Session,
     actix_web::{
         get,
@@ -35,6 +35,16 @@ struct Query {
     path: Option<String>,
 }
 
+impl Query {
+    /// Where a logged in user visiting `/login?path=` is sent, only paths on this site are allowed.
+    fn redirect(&self) -> String {
+        self.path
+            .as_deref()
+            .and_then(redirect::validate)
+            .unwrap_or_else(|| String::from("/"))
+    }
+}
+
 #[cfg(debug_assertions)]
 mod debug {
     use {super::*, actix_files::NamedFile, std::path::Path};
@@ -60,6 +70,7 @@ mod debug {
     }
 
     pub(super) async fn serve_file(
+        data: web::Data<AppData>,
         req: HttpRequest,
         session: Session,
         query: web::Query<Query>,
@@ -70,10 +81,10 @@ mod debug {
         let path = req.path().trim_matches('/');
 
         match PATHS.find(path) {
-            Some(_) if SessionUser::try_from(&session).is_ok() => {
+            Some(_) if SessionUser::from_session(&session, &data).is_ok() => {
                 if path == "login" {
                     Ok(HttpResponse::TemporaryRedirect()
-                        .append_header(("location", query.path.as_deref().unwrap_or("/")))
+                        .append_header(("location", query.redirect()))
                         .finish())
                 } else {
                     Ok(get_file("index.html", &req))
@@ -122,6 +133,7 @@ mod release {
     }
 
     pub(super) async fn serve_file(
+        data: web::Data<AppData>,
         req: HttpRequest,
         session: Session,
         query: web::Query<Query>,
@@ -132,13 +144,10 @@ mod release {
         let path = req.path().trim_matches('/');
 
         match PATHS.find(path) {
-            Some(_) if SessionUser::try_from(&session).is_ok() => {
+            Some(_) if SessionUser::from_session(&session, &data).is_ok() => {
                 if path == "login" {
                     Ok(HttpResponse::TemporaryRedirect()
-                        .append_header((
-                            "location",
-                            query.path.as_ref().map(String::as_str).unwrap_or("/"),
-                        ))
+                        .append_header(("location", query.redirect()))
                         .finish())
                 } else {
                     Ok(get_file("index.html", &req))
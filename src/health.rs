@@ -0,0 +1,96 @@
+use {
+    crate::{storage, AppData},
+    actix_web::{get, rt, web, HttpResponse, Responder},
+    serde::Serialize,
+    std::{sync::atomic::Ordering, time::Duration},
+};
+
+/// Providers taking longer than this to answer are considered unreachable.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+pub struct Check {
+    pub name: String,
+    /// Why the check failed, `None` if it passed.
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<(), String>) -> Self {
+        Self {
+            name: name.into(),
+            error: result.err(),
+        }
+    }
+}
+
+/// Everything that has to work for the server to take traffic.
+pub async fn checks(data: &AppData) -> Vec<Check> {
+    let dir = data.data_dir.clone();
+    let storage = match web::block(move || storage::check_writable(&dir)).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    let mut checks = vec![
+        Check::new(
+            "shutdown",
+            match data.shutting_down.load(Ordering::Relaxed) {
+                true => Err(String::from("Shutting down")),
+                false => Ok(()),
+            },
+        ),
+        Check::new("storage", storage),
+    ];
+    for provider in data.auth_providers.iter() {
+        let result = match rt::time::timeout(CHECK_TIMEOUT, provider.check()).await {
+            Ok(result) => result.map_err(|err| err.to_string()),
+            Err(_) => Err(String::from("Timed out")),
+        };
+        checks.push(Check::new(format!("auth:{}", provider.name()), result));
+    }
+    checks
+}
+
+/// The process is up and serving requests.
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+/// The server can take traffic. Failures are only detailed in the admin status and the log.
+#[get("/readyz")]
+async fn readyz(data: web::Data<AppData>) -> impl Responder {
+    let checks = checks(&data).await;
+    let failed: Vec<&str> = checks
+        .iter()
+        .filter_map(|check| {
+            let error = check.error.as_ref()?;
+            tracing::warn!(check = %check.name, %error, "Readiness check failed");
+            Some(check.name.as_str())
+        })
+        .collect();
+    match failed.is_empty() {
+        true => HttpResponse::Ok().body("ok"),
+        false => HttpResponse::ServiceUnavailable().body(format!("failed: {}", failed.join(", "))),
+    }
+}
+
+/// `sync-play health-check`, for container health checks without needing curl in the image.
+pub async fn check_command(port: u16) -> std::io::Result<()> {
+    let url = format!("http://127.0.0.1:{port}/healthz");
+    match reqwest::Client::new()
+        .get(&url)
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(std::io::Error::other(format!("{url}: {err}"))),
+    }
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(healthz);
+    cfg.service(readyz);
+}
@@ -1,63 +1,163 @@
-use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::SameSite, App, HttpServer};
+use actix_session::SessionMiddleware;
+use actix_web::{cookie::SameSite, dev::Service, rt, web, App, HttpServer};
+use clap::Parser;
 
+mod admin;
 mod app_data;
+mod auth;
+mod clock;
+mod config;
 mod error;
+mod events;
 mod frontend;
-mod user;
+mod health;
+mod media;
+mod metrics;
+mod pictures;
+mod preferences;
+mod rate_limit;
+mod redirect;
+mod request_id;
 mod room;
+mod session_key;
+mod sessions;
+mod shutdown;
+mod storage;
+mod subtitles;
+mod telemetry;
+mod tokens;
+mod user;
+mod users;
 
 pub(crate) use app_data::AppData;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    if cfg!(debug_assertions) {
-        std::env::set_var("RUST_BACKTRACE", "1");
-        env_logger::builder()
-            .filter_level(log::LevelFilter::Debug)
-            .init();
-    } else {
-        env_logger::builder()
-            .filter_level(log::LevelFilter::Info)
-            .init();
-    }
-
-    let secret_key = if cfg!(debug_assertions) {
-        const KEY: [u8; 64] = [
-            214, 235, 254, 208, 2, 104, 84, 123, 188, 216, 236, 30, 146, 156, 213, 15, 147, 35,
-            130, 11, 141, 202, 130, 20, 211, 63, 205, 136, 81, 195, 0, 80, 80, 42, 206, 22, 171,
-            158, 238, 37, 98, 227, 20, 175, 117, 41, 12, 238, 110, 162, 252, 129, 230, 118, 61,
-            122, 20, 108, 234, 140, 246, 149, 111, 174,
-        ];
-        actix_web::cookie::Key::from(&KEY)
-    } else {
-        actix_web::cookie::Key::generate()
+    let cli = config::Cli::parse();
+    let config = match config::Config::load(&cli) {
+        Ok(config) => config,
+        Err(errors) => exit_with_errors("Invalid configuration:", &errors),
     };
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return Ok(());
+    }
 
-    unsafe {
-        AppData::init().await;
+    match &cli.command {
+        Some(config::Command::AddUser {
+            username,
+            display_name,
+        }) => return auth::local::add_user_command(&config, username, display_name.as_deref()),
+        Some(config::Command::RotateSessionKey) => return session_key::rotate_command(&config),
+        Some(config::Command::HealthCheck) => return health::check_command(config.port).await,
+        None => (),
     }
 
-    let port = std::env::var("PORT")
-        .map(|x| x.parse().expect("Invalid port"))
-        .unwrap_or_else(|_| 80);
+    if cfg!(debug_assertions) {
+        std::env::set_var("RUST_BACKTRACE", "1");
+    }
+    let mut errors = Vec::new();
+    let telemetry = telemetry::init(&config.log).map_err(|err| errors.push(err));
+    let session_keys =
+        session_key::SessionKeys::from_config(&config).map_err(|err| errors.push(err));
+    let app_data = AppData::new(&config).map_err(|errs| errors.extend(errs));
+    let (Ok(telemetry), Ok(session_keys), Ok(mut app_data)) = (telemetry, session_keys, app_data)
+    else {
+        exit_with_errors("Unable to start:", &errors);
+    };
+    app_data.log_filter = Some(telemetry.filter.clone());
+    let data = web::Data::new(app_data);
 
-    HttpServer::new(move || {
+    let server_data = data.clone();
+    let server = HttpServer::new(move || {
+        let data = server_data.clone();
+        let session_keys = session_keys.clone();
+        let metrics = data.metrics.clone();
         App::new()
-            .wrap(actix_web::middleware::Logger::default())
+            .app_data(data.clone())
+            // innermost, so the session middleware still sees the error responses it replaces
+            .wrap_fn(|req, srv| {
+                let res = srv.call(req);
+                async move { Ok(error::problem_details(res.await?)) }
+            })
+            .wrap(actix_web::middleware::Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}i"#,
+            ))
             .wrap(
-                SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
+                SessionMiddleware::builder(data.sessions.clone(), session_keys.current.clone())
+                    .cookie_name(session_key::COOKIE_NAME.to_owned())
                     .cookie_http_only(true)
                     .cookie_same_site(SameSite::Strict)
                     .cookie_secure(cfg!(not(debug_assertions)))
                     .build(),
             )
+            // sessions signed with the previous key are re-signed until its grace period ends
+            .wrap_fn(move |mut req, srv| {
+                let upgraded = session_keys.upgrade_cookie(&mut req);
+                let res = srv.call(req);
+                async move {
+                    let mut res = res.await?;
+                    let session_cookie_set = res
+                        .response()
+                        .cookies()
+                        .any(|cookie| cookie.name() == session_key::COOKIE_NAME);
+                    if let (Some(cookie), false) = (upgraded, session_cookie_set) {
+                        res.response_mut().add_cookie(&cookie)?;
+                    }
+                    Ok(res)
+                }
+            })
+            .wrap_fn(move |req, srv| {
+                let start = std::time::Instant::now();
+                let method = req.method().clone();
+                let res = srv.call(req);
+                let metrics = metrics.clone();
+                async move {
+                    let res = res.await?;
+                    metrics.observe_request(&method, &res, start);
+                    Ok(res)
+                }
+            })
+            // outermost, so everything logged while handling a request carries its id
+            .wrap_fn(request_id::middleware)
+            .configure(health::init)
+            .configure(metrics::init)
             .configure(user::init)
+            .configure(sessions::init)
+            .configure(tokens::init)
+            .configure(preferences::init)
+            .configure(auth::local::init)
+            .configure(auth::dev::init)
             .configure(frontend::init)
             .configure(room::init)
+            .configure(media::init)
+            .configure(pictures::init)
+            .configure(admin::init)
     })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+    .bind(("0.0.0.0", config.port))?
+    .disable_signals()
+    .shutdown_timeout(config.shutdown.deadline)
+    .run();
+
+    let sessions = data.sessions.clone();
+    rt::spawn(shutdown::on_signal(
+        server.handle(),
+        data,
+        config.shutdown.clone(),
+    ));
+    let result = server.await;
+    if let Err(err) = sessions.flush() {
+        tracing::error!(%err, "Unable to save sessions");
+    }
+    telemetry.shutdown();
+    result
+}
+
+fn exit_with_errors(heading: &str, errors: &[String]) -> ! {
+    eprintln!("{heading}");
+    for error in errors {
+        eprintln!("  {error}");
+    }
+    std::process::exit(1);
 }
@@ -0,0 +1,346 @@
+use {
+    crate::{
+        clock::now,
+        config::{self, MediaConfig},
+        error::{forbidden, not_found},
+        tokens::Scope,
+        user::SessionUser,
+        AppData,
+    },
+    actix_files::NamedFile,
+    actix_web::{get, web, Error, HttpRequest, HttpResponse, Responder},
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    hmac::{Hmac, Mac},
+    rand::RngCore,
+    serde::{Deserialize, Serialize},
+    sha2::Sha256,
+    std::{
+        path::{Component, Path, PathBuf},
+        time::Duration,
+    },
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Media files the server is allowed to stream, together with the key used to sign their URLs.
+pub struct MediaLibrary {
+    root: PathBuf,
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+#[derive(Serialize)]
+pub struct SignedMedia {
+    pub path: String,
+    pub url: String,
+    pub expires: u64,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    user: u32,
+    expires: u64,
+    signature: String,
+}
+
+impl MediaLibrary {
+    /// Returns `None` if `media.dir` is not set, which disables server side media.
+    pub fn from_config(config: &MediaConfig) -> Result<Option<Self>, String> {
+        let Some(root) = config.dir.as_ref() else {
+            return Ok(None);
+        };
+        let root = config::expand(root)
+            .canonicalize()
+            .map_err(|err| format!("Unable to open media directory {root:?}: {err}"))?;
+
+        let secret = match &config.url_secret {
+            Some(x) => x.expose().as_bytes().to_vec(),
+            None => {
+                let mut secret = vec![0; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+
+        Ok(Some(Self {
+            root,
+            secret,
+            ttl: Duration::from_secs(config.url_ttl),
+        }))
+    }
+
+    /// Resolves a library relative path, rejecting anything that would escape the library.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        let full_path = self.root.join(path).canonicalize().ok()?;
+        match full_path.starts_with(&self.root) && full_path.is_file() {
+            true => Some(full_path),
+            false => None,
+        }
+    }
+
+    fn mac(&self, room_id: u32, user_id: u32, expires: u64, path: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key size");
+        mac.update(format!("{room_id}:{user_id}:{expires}:{path}").as_bytes());
+        mac
+    }
+
+    /// Issues a URL that lets `user_id` stream `path` while they are a member of `room_id`.
+    pub fn sign(&self, room_id: u32, user_id: u32, path: &str) -> SignedMedia {
+        let expires = now() + self.ttl.as_secs();
+        let signature = URL_SAFE_NO_PAD.encode(
+            self.mac(room_id, user_id, expires, path)
+                .finalize()
+                .into_bytes(),
+        );
+        SignedMedia {
+            path: path.to_owned(),
+            url: format!(
+                "/api/rooms/{room_id}/media/stream?user={user_id}&expires={expires}&signature={signature}"
+            ),
+            expires,
+        }
+    }
+
+    fn verify(&self, room_id: u32, path: &str, signature: &Signature) -> bool {
+        if signature.expires < now() {
+            return false;
+        }
+        let Ok(bytes) = URL_SAFE_NO_PAD.decode(&signature.signature) else {
+            return false;
+        };
+        self.mac(room_id, signature.user, signature.expires, path)
+            .verify_slice(&bytes)
+            .is_ok()
+    }
+}
+
+/// Lists every file under `root` as a path relative to it. Symlinks are skipped, so links
+/// out of the library or back up the tree aren't followed. Blocks on the file system.
+fn list_files(root: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    files.push(relative.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+pub fn library(data: &AppData) -> Result<&MediaLibrary, Error> {
+    data.media
+        .as_ref()
+        .ok_or_else(|| not_found("media_library_disabled", "No media library configured"))
+}
+
+#[get("/api/media")]
+async fn list(data: web::Data<AppData>, user: SessionUser) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
+    let root = library(&data)?.root.clone();
+    Ok(web::Json(web::block(move || list_files(&root)).await?))
+}
+
+#[get("/api/rooms/{id}/media/stream")]
+async fn stream(
+    data: web::Data<AppData>,
+    req: HttpRequest,
+    id: web::Path<u32>,
+    signature: web::Query<Signature>,
+) -> Result<HttpResponse, Error> {
+    let library = library(&data)?;
+    let room_id = id.into_inner();
+
+    let path = {
+        let rooms_guard = data.rooms.read().await;
+        let room = rooms_guard
+            .get(&room_id)
+            .ok_or_else(|| {
+                not_found(
+                    "room_not_found",
+                    format!("Room with id {room_id} not found"),
+                )
+            })?
+            .read()
+            .await;
+        match room.media() {
+            Some(path) if room.has_user(signature.user) => path.to_owned(),
+            _ => return Err(forbidden("invalid_media_url", "Invalid media url")),
+        }
+    };
+
+    if !library.verify(room_id, &path, &signature) {
+        return Err(forbidden("invalid_media_url", "Invalid media url"));
+    }
+
+    let file = library
+        .resolve(&path)
+        .ok_or_else(|| not_found("media_not_found", "Media not found"))?;
+    Ok(NamedFile::open_async(file).await?.into_response(&req))
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(stream);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A library in a fresh temporary directory, holding `movie.mp4` and `shows/episode.mkv`.
+    fn library(name: &str) -> MediaLibrary {
+        let dir =
+            std::env::temp_dir().join(format!("sync-play-media-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("library/shows")).unwrap();
+        std::fs::write(dir.join("library/movie.mp4"), b"movie").unwrap();
+        std::fs::write(dir.join("library/shows/episode.mkv"), b"episode").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+        MediaLibrary {
+            root: dir.join("library").canonicalize().unwrap(),
+            secret: b"test secret".to_vec(),
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    fn signature(signed: &SignedMedia) -> Signature {
+        let query = signed.url.split_once('?').unwrap().1;
+        let param = |name: &str| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+                .unwrap()
+                .to_owned()
+        };
+        Signature {
+            user: param("user").parse().unwrap(),
+            expires: param("expires").parse().unwrap(),
+            signature: param("signature"),
+        }
+    }
+
+    #[test]
+    fn verifies_signed_urls() {
+        let library = library("verify");
+        let signed = library.sign(1, 2, "movie.mp4");
+        assert_eq!(signed.path, "movie.mp4");
+        assert!(signed.expires > now());
+        assert!(library.verify(1, "movie.mp4", &signature(&signed)));
+    }
+
+    #[test]
+    fn rejects_tampered_signatures() {
+        let library = library("tampered");
+        let signed = library.sign(1, 2, "movie.mp4");
+        assert!(!library.verify(3, "movie.mp4", &signature(&signed)));
+        assert!(!library.verify(1, "shows/episode.mkv", &signature(&signed)));
+
+        let other_user = Signature {
+            user: 4,
+            ..signature(&signed)
+        };
+        assert!(!library.verify(1, "movie.mp4", &other_user));
+        let later = Signature {
+            expires: signed.expires + 60,
+            ..signature(&signed)
+        };
+        assert!(!library.verify(1, "movie.mp4", &later));
+        let invalid = Signature {
+            signature: String::from("not base64!"),
+            ..signature(&signed)
+        };
+        assert!(!library.verify(1, "movie.mp4", &invalid));
+    }
+
+    #[test]
+    fn rejects_expired_signatures() {
+        let library = library("expired");
+        let expires = now() - 1;
+        let signature = Signature {
+            user: 2,
+            expires,
+            signature: URL_SAFE_NO_PAD.encode(
+                library
+                    .mac(1, 2, expires, "movie.mp4")
+                    .finalize()
+                    .into_bytes(),
+            ),
+        };
+        assert!(!library.verify(1, "movie.mp4", &signature));
+    }
+
+    #[test]
+    fn resolves_files_in_the_library() {
+        let library = library("resolve");
+        assert_eq!(
+            library.resolve("movie.mp4"),
+            Some(library.root.join("movie.mp4"))
+        );
+        assert_eq!(
+            library.resolve("shows/episode.mkv"),
+            Some(library.root.join("shows/episode.mkv"))
+        );
+        assert_eq!(library.resolve("missing.mp4"), None);
+        assert_eq!(library.resolve("shows"), None);
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_library() {
+        let library = library("traversal");
+        for path in [
+            "../secret.txt",
+            "shows/../../secret.txt",
+            "shows/../movie.mp4",
+            "./movie.mp4",
+            "/etc/passwd",
+        ] {
+            assert_eq!(library.resolve(path), None, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn lists_files_relative_to_the_root() {
+        let library = library("list");
+        assert_eq!(
+            list_files(&library.root),
+            ["movie.mp4", "shows/episode.mkv"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn list_skips_symlinks() {
+        let library = library("symlinks");
+        std::os::unix::fs::symlink(&library.root, library.root.join("shows/loop")).unwrap();
+        std::os::unix::fs::symlink(
+            library.root.join("../secret.txt"),
+            library.root.join("secret.txt"),
+        )
+        .unwrap();
+        assert_eq!(
+            list_files(&library.root),
+            ["movie.mp4", "shows/episode.mkv"]
+        );
+    }
+}
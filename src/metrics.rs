@@ -0,0 +1,183 @@
+use {
+    crate::{
+        config::MetricsConfig,
+        error::{not_found, unauthorized},
+        AppData,
+    },
+    actix_web::{
+        dev::ServiceResponse,
+        get,
+        http::{header, Method},
+        web, Error, HttpRequest, HttpResponse,
+    },
+    prometheus::{
+        exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+        IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+    },
+    sha2::{Digest, Sha256},
+    std::time::Instant,
+};
+
+/// Prometheus metrics of one server instance.
+pub struct Metrics {
+    registry: Registry,
+    /// Bearer token required to scrape `/metrics`, if any.
+    token: Option<String>,
+    enabled: bool,
+    rooms: IntGauge,
+    sockets: IntGauge,
+    /// Per recipient, labeled with the socket command.
+    pub messages_relayed: IntCounterVec,
+    pub bytes_received: IntCounter,
+    pub bytes_sent: IntCounter,
+    pub send_failures: IntCounter,
+    pub room_lifetime: Histogram,
+    pub auth_callback: HistogramVec,
+    http_requests: HistogramVec,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(registry: &Registry, metric: T) -> T {
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+impl Metrics {
+    pub fn new(config: &MetricsConfig) -> Self {
+        let registry = Registry::new_custom(Some(String::from("sync_play")), None)
+            .expect("Invalid metrics prefix");
+        Self {
+            token: config.token.as_ref().map(|token| token.expose().to_owned()),
+            enabled: config.enabled,
+            rooms: register(
+                &registry,
+                IntGauge::new("rooms", "Rooms currently open").unwrap(),
+            ),
+            sockets: register(
+                &registry,
+                IntGauge::new("sockets", "Sockets currently connected to a room").unwrap(),
+            ),
+            messages_relayed: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "messages_relayed_total",
+                        "Socket messages sent to room members",
+                    ),
+                    &["command"],
+                )
+                .unwrap(),
+            ),
+            bytes_received: register(
+                &registry,
+                IntCounter::new("socket_received_bytes_total", "Bytes received on sockets")
+                    .unwrap(),
+            ),
+            bytes_sent: register(
+                &registry,
+                IntCounter::new("socket_sent_bytes_total", "Bytes sent on sockets").unwrap(),
+            ),
+            send_failures: register(
+                &registry,
+                IntCounter::new(
+                    "socket_send_failures_total",
+                    "Messages that couldn't be sent to a room member",
+                )
+                .unwrap(),
+            ),
+            room_lifetime: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new("room_lifetime_seconds", "How long rooms stay open")
+                        .buckets(exponential_buckets(60.0, 2.0, 12).unwrap()),
+                )
+                .unwrap(),
+            ),
+            auth_callback: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "auth_callback_duration_seconds",
+                        "Time taken to handle login callbacks",
+                    ),
+                    &["provider"],
+                )
+                .unwrap(),
+            ),
+            http_requests: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "http_request_duration_seconds",
+                        "Time taken to answer HTTP requests",
+                    ),
+                    &["method", "route", "status"],
+                )
+                .unwrap(),
+            ),
+            registry,
+        }
+    }
+
+    fn authorized(&self, req: &HttpRequest) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let given = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // comparing digests doesn't leak how much of the token matched
+        given.is_some_and(|given| Sha256::digest(given) == Sha256::digest(token))
+    }
+
+    /// Labeled with the route pattern rather than the path, to keep the number of series bounded.
+    pub fn observe_request<B>(&self, method: &Method, res: &ServiceResponse<B>, start: Instant) {
+        let route = res.request().match_pattern();
+        self.http_requests
+            .with_label_values(&[
+                method.as_str(),
+                route.as_deref().unwrap_or("unmatched"),
+                res.status().as_str(),
+            ])
+            .observe(start.elapsed().as_secs_f64());
+    }
+}
+
+#[get("/metrics")]
+async fn scrape(data: web::Data<AppData>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let metrics = &data.metrics;
+    if !metrics.enabled {
+        return Err(not_found("metrics_disabled", "Metrics are disabled"));
+    }
+    if !metrics.authorized(&req) {
+        return Err(unauthorized(
+            "invalid_metrics_token",
+            "Invalid metrics token",
+        ));
+    }
+
+    let rooms_guard = data.rooms.read().await;
+    let mut sockets = 0;
+    for room in rooms_guard.values() {
+        sockets += room.read().await.socket_count();
+    }
+    metrics.rooms.set(rooms_guard.len() as i64);
+    metrics.sockets.set(sockets as i64);
+    drop(rooms_guard);
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&metrics.registry.gather(), &mut body)
+        .expect("Unable to encode metrics");
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body))
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(scrape);
+}
@@ -0,0 +1,243 @@
+use {
+    crate::{config::PicturesConfig, error::ToErr, user::SessionUser, AppData},
+    actix_web::{
+        get,
+        http::header::{self, EntityTag},
+        web, Error, HttpRequest, HttpResponse, Responder,
+    },
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    image::{imageops::FilterType, ImageFormat, ImageReader, Limits},
+    serde::Deserialize,
+    sha2::{Digest, Sha256},
+    std::{
+        io::Cursor,
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    },
+};
+
+/// Pictures are resized to all of these, requests get the smallest one that's large enough.
+const SIZES: [u32; 4] = [32, 64, 128, 256];
+const DEFAULT_SIZE: u32 = 64;
+/// Larger pictures are rejected before they're decoded.
+const MAX_DIMENSION: u32 = 4096;
+/// Memory decoding a picture may take.
+const MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
+
+/// Profile pictures fetched from the auth providers, cached on disk.
+pub struct PictureCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+enum Picture {
+    Png(Vec<u8>),
+    /// The user has no picture, they get a generated avatar instead.
+    Default,
+}
+
+impl PictureCache {
+    pub fn from_config(config: &PicturesConfig, data_dir: &Path) -> Self {
+        Self {
+            dir: data_dir.join("pictures"),
+            ttl: Duration::from_secs(config.cache_ttl),
+        }
+    }
+
+    fn path(&self, user_id: u32, size: u32) -> PathBuf {
+        self.dir.join(format!("{user_id}-{size}.png"))
+    }
+
+    /// Marks users without a picture, so the provider isn't asked again until the entry expires.
+    fn missing_path(&self, user_id: u32) -> PathBuf {
+        self.dir.join(format!("{user_id}.missing"))
+    }
+
+    async fn get(&self, data: &AppData, user_id: u32, size: u32) -> Result<Picture, Error> {
+        let path = self.path(user_id, size);
+        let missing = self.missing_path(user_id);
+        let ttl = self.ttl;
+        if let Some(picture) = web::block(move || cached(&path, &missing, ttl))
+            .await?
+            .to_err()?
+        {
+            return Ok(picture);
+        }
+
+        let Some(original) = fetch(data, user_id).await? else {
+            let dir = self.dir.clone();
+            let missing = self.missing_path(user_id);
+            web::block(move || {
+                std::fs::create_dir_all(dir)?;
+                std::fs::write(missing, [])
+            })
+            .await?
+            .to_err()?;
+            return Ok(Picture::Default);
+        };
+
+        let paths: Vec<(u32, PathBuf)> = SIZES
+            .iter()
+            .map(|size| (*size, self.path(user_id, *size)))
+            .collect();
+        let dir = self.dir.clone();
+        let requested = size;
+        let png = web::block(move || -> Result<Vec<u8>, image::ImageError> {
+            let image = decode(&original)?;
+            std::fs::create_dir_all(dir)?;
+            let mut requested_png = Vec::new();
+            for (size, path) in paths {
+                let mut png = Vec::new();
+                image
+                    .resize_to_fill(size, size, FilterType::Lanczos3)
+                    .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+                std::fs::write(path, &png)?;
+                if size == requested {
+                    requested_png = png;
+                }
+            }
+            Ok(requested_png)
+        })
+        .await?
+        .map_err(|err| {
+            tracing::warn!(user_id, error = %err, "Unable to process profile picture");
+            crate::error::bad_gateway("invalid_picture", "Invalid profile picture")
+        })?;
+
+        Ok(Picture::Png(png))
+    }
+}
+
+/// Decodes a picture from a provider, within limits so it can't exhaust memory.
+fn decode(picture: &[u8]) -> Result<image::DynamicImage, image::ImageError> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = ImageReader::new(Cursor::new(picture)).with_guessed_format()?;
+    reader.limits(limits);
+    reader.decode()
+}
+
+fn fresh(path: &Path, ttl: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default()
+                < ttl
+        })
+        .unwrap_or(false)
+}
+
+/// The cached picture or missing marker, if it hasn't expired. Blocks on the file system.
+fn cached(path: &Path, missing: &Path, ttl: Duration) -> std::io::Result<Option<Picture>> {
+    if fresh(path, ttl) {
+        return Ok(Some(Picture::Png(std::fs::read(path)?)));
+    }
+    if fresh(missing, ttl) {
+        return Ok(Some(Picture::Default));
+    }
+    Ok(None)
+}
+
+/// Asks the provider that knows the user for their picture, unknown users have none.
+async fn fetch(data: &AppData, user_id: u32) -> Result<Option<Vec<u8>>, Error> {
+    let Some(user) = data.users.get(user_id) else {
+        return Ok(None);
+    };
+    match data.auth_providers.get(&user.provider) {
+        Some(provider) => provider.profile_picture(&data.users, user_id).await,
+        None => Ok(None),
+    }
+}
+
+/// A circle with the user's initial, colored by their id.
+fn default_avatar(data: &AppData, user_id: u32) -> String {
+    let initial = data
+        .users
+        .get(user_id)
+        .and_then(|user| user.name.chars().find(|c| c.is_alphanumeric()))
+        .map(|c| c.to_uppercase().to_string())
+        .unwrap_or_else(|| String::from("?"));
+    let hue = user_id.wrapping_mul(2654435761) % 360;
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 64 64\">\
+        <circle cx=\"32\" cy=\"32\" r=\"32\" fill=\"hsl({hue}, 55%, 45%)\"/>\
+        <text x=\"32\" y=\"32\" dy=\".35em\" text-anchor=\"middle\" font-family=\"sans-serif\" \
+        font-size=\"30\" fill=\"#fff\">{initial}</text></svg>"
+    )
+}
+
+#[derive(Deserialize)]
+struct PictureQuery {
+    size: Option<u32>,
+}
+
+#[get("/auth/users/{id}/picture")]
+async fn get_profile_picture(
+    data: web::Data<AppData>,
+    req: HttpRequest,
+    _user: SessionUser,
+    id: web::Path<u32>,
+    query: web::Query<PictureQuery>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+    let requested = query.size.unwrap_or(DEFAULT_SIZE);
+    let size = SIZES
+        .into_iter()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1]);
+
+    let cache = &data.pictures;
+    let (body, content_type) = match cache.get(&data, id, size).await? {
+        Picture::Png(png) => (png, "image/png"),
+        Picture::Default => (default_avatar(&data, id).into_bytes(), "image/svg+xml"),
+    };
+
+    let etag = EntityTag::new_strong(URL_SAFE_NO_PAD.encode(&Sha256::digest(&body)[..16]));
+    let cache_control = format!("private, max-age={}", cache.ttl.as_secs());
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag.to_string()));
+    if not_modified {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header((header::CACHE_CONTROL, cache_control))
+            .finish());
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(header::ETag(etag))
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .body(body))
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(get_profile_picture);
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, image::RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn decodes_pictures_within_limits() {
+        let image = decode(&png(64, 32)).unwrap();
+        assert_eq!((image.width(), image.height()), (64, 32));
+        assert!(decode(&png(MAX_DIMENSION + 1, 1)).is_err());
+        assert!(decode(&png(1, MAX_DIMENSION + 1)).is_err());
+        assert!(decode(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use {
+    crate::{
+        error::{ApiError, ToErr},
+        storage,
+        user::{valid_name, SessionUser},
+        AppData,
+    },
+    actix_session::Session,
+    actix_web::{get, http::StatusCode, put, web, Error, Responder},
+    serde::{Deserialize, Serialize},
+    serde_json::json,
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+const FILE: &str = "preferences.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields, default)]
+pub struct Preferences {
+    /// Shown to other room members instead of the provider's name.
+    pub display_name: Option<String>,
+    /// Subtitle tracks in this language are enabled by default, e.g. `en` or `pt-BR`.
+    pub subtitle_language: Option<String>,
+    /// How far, in milliseconds, playback may drift from the room before it's corrected.
+    pub sync_tolerance: u32,
+    pub chat_notifications: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            display_name: None,
+            subtitle_language: None,
+            sync_tolerance: 200,
+            chat_notifications: true,
+        }
+    }
+}
+
+/// Names the offending field in the details, so the form can highlight it.
+fn invalid(field: &str, message: &str) -> Error {
+    ApiError::new(StatusCode::BAD_REQUEST, "invalid_preferences", message)
+        .with_details(json!({ "field": field }))
+        .into()
+}
+
+impl Preferences {
+    fn validate(mut self) -> Result<Self, Error> {
+        self.display_name = self
+            .display_name
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty());
+        if let Some(name) = &self.display_name {
+            if !valid_name(name) {
+                return Err(invalid(
+                    "displayName",
+                    "Display names must be at most 32 characters long",
+                ));
+            }
+        }
+
+        self.subtitle_language = self
+            .subtitle_language
+            .map(|language| language.trim().to_owned())
+            .filter(|language| !language.is_empty());
+        if let Some(language) = &self.subtitle_language {
+            let mut parts = language.split('-');
+            let primary = parts.next().unwrap_or_default();
+            let valid = (2..=3).contains(&primary.len())
+                && primary.chars().all(|c| c.is_ascii_alphabetic())
+                && parts.all(|part| {
+                    (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+                });
+            if !valid {
+                return Err(invalid(
+                    "subtitleLanguage",
+                    "Subtitle language must be a language tag like \"en\" or \"pt-BR\"",
+                ));
+            }
+        }
+
+        if !(50..=5000).contains(&self.sync_tolerance) {
+            return Err(invalid(
+                "syncTolerance",
+                "Sync tolerance must be between 50 and 5000 milliseconds",
+            ));
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PreferencesFile {
+    users: HashMap<u32, Preferences>,
+}
+
+/// Per-user settings, users who never changed theirs get the defaults.
+pub struct UserPreferences {
+    path: PathBuf,
+    file: Mutex<PreferencesFile>,
+}
+
+impl UserPreferences {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(FILE);
+        Ok(Self {
+            file: Mutex::new(storage::load(&path)?),
+            path,
+        })
+    }
+
+    pub fn get(&self, user_id: u32) -> Preferences {
+        let file = self.file.lock().unwrap();
+        file.users.get(&user_id).cloned().unwrap_or_default()
+    }
+
+    fn set(&self, user_id: u32, preferences: Preferences) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        match preferences == Preferences::default() {
+            true => file.users.remove(&user_id),
+            false => file.users.insert(user_id, preferences),
+        };
+        storage::save(&self.path, &*file)
+    }
+
+    /// The name `user` is shown with in rooms.
+    pub fn display_name(&self, user: &SessionUser) -> String {
+        self.get(user.id)
+            .display_name
+            .unwrap_or_else(|| user.name.clone())
+    }
+}
+
+/// Preferences belong to the browser session, guests don't keep theirs past it.
+fn session_user(session: &Session, data: &AppData) -> Result<SessionUser, Error> {
+    SessionUser::from_session_non_guest(session, data, "Guests don't have preferences")
+}
+
+#[get("/api/users/me/preferences")]
+async fn get_preferences(
+    data: web::Data<AppData>,
+    session: Session,
+) -> Result<impl Responder, Error> {
+    let user = session_user(&session, &data)?;
+    Ok(web::Json(data.preferences.get(user.id)))
+}
+
+#[put("/api/users/me/preferences")]
+async fn set_preferences(
+    data: web::Data<AppData>,
+    session: Session,
+    body: web::Json<Preferences>,
+) -> Result<impl Responder, Error> {
+    let user = session_user(&session, &data)?;
+    let preferences = body.into_inner().validate()?;
+    data.preferences
+        .set(user.id, preferences.clone())
+        .to_err()?;
+    Ok(web::Json(preferences))
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(get_preferences);
+    cfg.service(set_preferences);
+}
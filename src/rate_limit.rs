@@ -0,0 +1,334 @@
+use {
+    crate::room::Command,
+    serde::{Deserialize, Deserializer, Serialize, Serializer},
+    std::{
+        collections::HashMap,
+        fmt,
+        hash::Hash,
+        str::FromStr,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Allows `count` requests per `period`, refilling continuously.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub count: u32,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub const fn new(count: u32, seconds: u64) -> Self {
+        Self {
+            count,
+            period: Duration::from_secs(seconds),
+        }
+    }
+
+    pub fn bucket(self) -> TokenBucket {
+        TokenBucket {
+            limit: self,
+            tokens: self.count as f64,
+            last: Instant::now(),
+        }
+    }
+}
+
+/// `<count>/<seconds>`, in the configuration file and environment.
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('/')
+            .and_then(|(count, seconds)| {
+                Some(Self::new(
+                    count.trim().parse().ok()?,
+                    seconds.trim().parse().ok()?,
+                ))
+            })
+            .filter(|limit| limit.count > 0 && !limit.period.is_zero())
+            .ok_or_else(|| format!("Invalid limit {s:?}, expected <count>/<seconds>"))
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.count, self.period.as_secs())
+    }
+}
+
+impl Serialize for Limit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: Limit,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        let refill = now.duration_since(self.last).as_secs_f64() / self.limit.period.as_secs_f64()
+            * self.limit.count as f64;
+        self.tokens = (self.tokens + refill).min(self.limit.count as f64);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub playback: Limit,
+    pub update_time: Limit,
+    pub ping: Limit,
+    pub invalid: Limit,
+    /// Dropped frames per minute before the socket is closed.
+    pub max_violations: u32,
+    #[serde(rename = "ws_max_frame_size")]
+    pub max_frame_size: usize,
+    pub room_creation: Limit,
+    pub subtitle_upload: Limit,
+    /// Per client address, for local account logins, registrations and password changes.
+    pub login: Limit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            playback: Limit::new(10, 5),
+            update_time: Limit::new(10, 1),
+            ping: Limit::new(5, 1),
+            invalid: Limit::new(3, 10),
+            max_violations: 20,
+            max_frame_size: 16 * 1024,
+            room_creation: Limit::new(10, 60 * 60),
+            subtitle_upload: Limit::new(20, 60 * 60),
+            login: Limit::new(10, 60),
+        }
+    }
+}
+
+pub enum Verdict {
+    Allow,
+    Drop,
+    /// The first dropped frame in a while, the client should be told.
+    Warn,
+    Disconnect,
+}
+
+/// Per-socket limits, one bucket per message type.
+pub struct SocketLimiter {
+    playback: TokenBucket,
+    update_time: TokenBucket,
+    ping: TokenBucket,
+    invalid: TokenBucket,
+    max_violations: u32,
+    violations: u32,
+    window_start: Instant,
+}
+
+impl SocketLimiter {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    pub fn new(limits: &RateLimits) -> Self {
+        Self {
+            playback: limits.playback.bucket(),
+            update_time: limits.update_time.bucket(),
+            ping: limits.ping.bucket(),
+            invalid: limits.invalid.bucket(),
+            max_violations: limits.max_violations,
+            violations: 0,
+            window_start: Instant::now(),
+        }
+    }
+
+    /// Checks a text frame, `None` being a frame that couldn't be parsed.
+    pub fn check(&mut self, command: Option<Command>) -> Verdict {
+        self.check_at(command, Instant::now())
+    }
+
+    fn check_at(&mut self, command: Option<Command>, now: Instant) -> Verdict {
+        let bucket = match command {
+            Some(Command::Ping) => &mut self.ping,
+            Some(Command::UpdateTime) => &mut self.update_time,
+            Some(_) => &mut self.playback,
+            None => &mut self.invalid,
+        };
+        if bucket.try_take_at(now) {
+            return Verdict::Allow;
+        }
+
+        if now.duration_since(self.window_start) > Self::WINDOW {
+            self.window_start = now;
+            self.violations = 0;
+        }
+        self.violations += 1;
+        match self.violations {
+            1 => Verdict::Warn,
+            x if x > self.max_violations => Verdict::Disconnect,
+            _ => Verdict::Drop,
+        }
+    }
+}
+
+/// Per-user limits for HTTP endpoints, or per client address where there's no user yet.
+pub struct UserLimiter<K = u32> {
+    limit: Limit,
+    buckets: Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K: Hash + Eq> UserLimiter<K> {
+    pub fn new(limit: Limit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn try_take(&self, key: K) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        // full buckets carry no state, drop them so the map doesn't grow forever
+        if buckets.len() > 1024 {
+            let limit = self.limit;
+            buckets.retain(|_, bucket| bucket.last.elapsed() < limit.period);
+        }
+        buckets
+            .entry(key)
+            .or_insert_with(|| self.limit.bucket())
+            .try_take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_limits() {
+        let limit: Limit = " 5 / 60 ".parse().unwrap();
+        assert_eq!((limit.count, limit.period), (5, Duration::from_secs(60)));
+        assert_eq!(limit.to_string(), "5/60");
+        for invalid in ["", "5", "0/60", "5/0", "a/60", "-1/60"] {
+            assert!(invalid.parse::<Limit>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn bucket_starts_full() {
+        let mut bucket = Limit::new(3, 10).bucket();
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let mut bucket = Limit::new(2, 10).bucket();
+        let start = bucket.last;
+        assert!(bucket.try_take_at(start));
+        assert!(bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start));
+
+        // one token every 5 seconds
+        assert!(!bucket.try_take_at(start + Duration::from_secs(3)));
+        assert!(bucket.try_take_at(start + Duration::from_secs(6)));
+        assert!(!bucket.try_take_at(start + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn bucket_never_exceeds_its_count() {
+        let mut bucket = Limit::new(2, 10).bucket();
+        let later = bucket.last + Duration::from_secs(3600);
+        assert!(bucket.try_take_at(later));
+        assert!(bucket.try_take_at(later));
+        assert!(!bucket.try_take_at(later));
+    }
+
+    #[test]
+    fn user_limiter_keeps_a_bucket_per_key() {
+        let limiter = UserLimiter::new(Limit::new(1, 60 * 60));
+        let (a, b): (std::net::IpAddr, _) =
+            ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        assert!(limiter.try_take(a));
+        assert!(!limiter.try_take(a));
+        assert!(limiter.try_take(b));
+    }
+
+    fn limiter() -> SocketLimiter {
+        SocketLimiter::new(&RateLimits {
+            // refills too slowly to matter within a test
+            playback: Limit::new(1, 60 * 60),
+            update_time: Limit::new(10, 1),
+            ping: Limit::new(5, 1),
+            invalid: Limit::new(3, 10),
+            max_violations: 3,
+            max_frame_size: 16 * 1024,
+            room_creation: Limit::new(10, 60 * 60),
+            subtitle_upload: Limit::new(20, 60 * 60),
+            login: Limit::new(10, 60),
+        })
+    }
+
+    #[test]
+    fn socket_limiter_escalates() {
+        let mut limiter = limiter();
+        let play = Some(Command::Play);
+        assert!(matches!(limiter.check(play), Verdict::Allow));
+        assert!(matches!(limiter.check(play), Verdict::Warn));
+        assert!(matches!(limiter.check(play), Verdict::Drop));
+        assert!(matches!(limiter.check(play), Verdict::Drop));
+        assert!(matches!(limiter.check(play), Verdict::Disconnect));
+    }
+
+    #[test]
+    fn socket_limiter_uses_a_bucket_per_command() {
+        let mut limiter = limiter();
+        assert!(matches!(limiter.check(Some(Command::Play)), Verdict::Allow));
+        assert!(matches!(limiter.check(Some(Command::Pause)), Verdict::Warn));
+        assert!(matches!(limiter.check(Some(Command::Ping)), Verdict::Allow));
+        assert!(matches!(
+            limiter.check(Some(Command::UpdateTime)),
+            Verdict::Allow
+        ));
+        assert!(matches!(limiter.check(None), Verdict::Allow));
+    }
+
+    #[test]
+    fn socket_limiter_forgets_old_violations() {
+        let mut limiter = limiter();
+        let play = Some(Command::Play);
+        let start = limiter.window_start;
+        assert!(matches!(limiter.check_at(play, start), Verdict::Allow));
+        assert!(matches!(limiter.check_at(play, start), Verdict::Warn));
+        assert!(matches!(limiter.check_at(play, start), Verdict::Drop));
+
+        let later = start + SocketLimiter::WINDOW / 2;
+        assert!(matches!(limiter.check_at(play, later), Verdict::Drop));
+        let next_window = start + SocketLimiter::WINDOW + Duration::from_secs(1);
+        assert!(matches!(limiter.check_at(play, next_window), Verdict::Warn));
+    }
+}
@@ -0,0 +1,115 @@
+use {crate::error::ToErr, actix_session::Session, actix_web::Error};
+
+const SESSION_KEY: &str = "redirect";
+
+/// Normalizes a post-login redirect target, only accepting paths on this site.
+///
+/// Rejects absolute and scheme-relative urls (`//host`, `/\host`), control characters
+/// and anything else a browser could resolve to another origin. `.` and `..` segments
+/// are resolved and empty segments dropped, so the result always starts with a single `/`.
+/// `..` above the root is rejected rather than clamped, only crafted links contain it.
+pub fn validate(target: &str) -> Option<String> {
+    if !target.starts_with('/')
+        || target.starts_with("//")
+        || target.contains('\\')
+        || target.chars().any(|c| c.is_control() || c.is_whitespace())
+    {
+        return None;
+    }
+
+    let (path, rest) = match target.find(['?', '#']) {
+        Some(index) => target.split_at(index),
+        None => (target, ""),
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(format!("/{}{rest}", segments.join("/")))
+}
+
+/// Remembers where to send the user once they're logged in, dropping invalid targets.
+pub fn remember(session: &Session, target: Option<&str>) -> Result<(), Error> {
+    match target.and_then(validate) {
+        Some(target) => session.insert(SESSION_KEY, target).to_err(),
+        None => {
+            session.remove(SESSION_KEY);
+            Ok(())
+        }
+    }
+}
+
+/// Takes the remembered target, `/` if there is none.
+pub fn take(session: &Session) -> String {
+    session
+        .remove_as::<String>(SESSION_KEY)
+        .and_then(Result::ok)
+        .and_then(|target| validate(&target))
+        .unwrap_or_else(|| String::from("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+
+    #[test]
+    fn rejects_other_origins() {
+        for target in [
+            "//evil.com",
+            "/\\evil.com",
+            "/..//evil.com",
+            "https://evil.com",
+            "javascript:",
+            "javascript:alert(1)",
+        ] {
+            assert_eq!(validate(target), None, "{target:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        for target in [
+            "/rooms\t/1",
+            "/rooms\n//evil.com",
+            "/\r\nLocation: x",
+            "/a\0b",
+            "/a b",
+        ] {
+            assert_eq!(validate(target), None, "{target:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_relative_paths() {
+        for target in ["", "rooms/1", "./rooms", "../rooms", "evil.com"] {
+            assert_eq!(validate(target), None, "{target:?}");
+        }
+    }
+
+    #[test]
+    fn normalizes_segments() {
+        assert_eq!(validate("/").as_deref(), Some("/"));
+        assert_eq!(validate("/rooms/1/../2").as_deref(), Some("/rooms/2"));
+        assert_eq!(validate("/rooms/./1/").as_deref(), Some("/rooms/1"));
+        assert_eq!(validate("/rooms//1").as_deref(), Some("/rooms/1"));
+        assert_eq!(validate("/a/b/../../c").as_deref(), Some("/c"));
+    }
+
+    #[test]
+    fn keeps_query_and_fragment() {
+        assert_eq!(
+            validate("/rooms/1/../2?media=a/../b#t=10").as_deref(),
+            Some("/rooms/2?media=a/../b#t=10")
+        );
+        assert_eq!(
+            validate("/?path=//evil.com").as_deref(),
+            Some("/?path=//evil.com")
+        );
+    }
+}
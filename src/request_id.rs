@@ -0,0 +1,68 @@
+use {
+    actix_web::{
+        dev::{Service, ServiceRequest, ServiceResponse},
+        http::header::{HeaderName, HeaderValue},
+        Error, HttpMessage, HttpRequest,
+    },
+    std::future::Future,
+    tracing::Instrument,
+};
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifies a request in logs, error responses and the `X-Request-Id` response header.
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn of(req: &HttpRequest) -> Option<String> {
+        req.extensions().get::<Self>().map(|id| id.0.clone())
+    }
+
+    fn generate() -> Self {
+        Self(format!("{:016x}", rand::random::<u64>()))
+    }
+
+    /// Ids set by a proxy in front of us are kept, as long as they can't mess up log lines.
+    fn accept(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = (1..=64).contains(&value.len())
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        valid.then(|| Self(value.to_owned()))
+    }
+}
+
+/// Assigns or accepts the request id and handles the request in a span carrying it,
+/// so every event logged while handling it includes the id.
+pub fn middleware<S, B>(
+    mut req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let id = req
+        .headers()
+        .get(HEADER)
+        .and_then(RequestId::accept)
+        .unwrap_or_else(RequestId::generate);
+    let value = HeaderValue::from_str(&id.0).expect("Request ids are valid header values");
+    // the access log reads it from the request
+    req.headers_mut().insert(HEADER, value.clone());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id.0,
+        method = %req.method(),
+        path = req.path(),
+    );
+    req.extensions_mut().insert(id);
+
+    let res = srv.call(req).instrument(span);
+    async move {
+        let mut res = res.await?;
+        res.headers_mut().insert(HEADER, value);
+        Ok(res)
+    }
+}
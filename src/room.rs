@@ -1,16 +1,94 @@
 use {
-    crate::{user::SessionUser, AppData},
-    actix_session::Session,
-    actix_web::{error::ErrorNotFound, get, post, rt, web, Error, HttpRequest, Responder},
-    actix_ws::Message,
+    crate::{
+        clock::timestamp,
+        error::{
+            bad_request, conflict, forbidden, not_found, service_unavailable, too_many_requests,
+        },
+        events::{EventKind, EventLog, RoomEvent},
+        media,
+        metrics::Metrics,
+        rate_limit::{SocketLimiter, Verdict},
+        request_id::RequestId,
+        subtitles::{self, SubtitleTrack},
+        tokens::Scope,
+        user::SessionUser,
+        AppData,
+    },
+    actix_web::{
+        get, http::header, post, put, rt, web, Error, HttpRequest, HttpResponse, Responder,
+    },
+    actix_ws::{CloseCode, CloseReason, Message, ProtocolError},
     futures_util::StreamExt,
     serde::{Deserialize, Serialize},
-    std::sync::atomic::AtomicU32,
+    std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
     tokio::sync::RwLock,
+    tracing::Instrument,
 };
 
 static ROOM_ID_INCREMENT: AtomicU32 = AtomicU32::new(1);
 static SOCKET_ID_INCREMENT: AtomicU32 = AtomicU32::new(1);
+static SUBTITLE_ID_INCREMENT: AtomicU32 = AtomicU32::new(1);
+/// Subtitle tracks a room keeps, across all the media it played.
+const MAX_SUBTITLE_TRACKS: usize = 20;
+
+/// Socket message types, encoded as `<command>;<data>`. Must match `PlayerCommands` in the frontend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Ping = 0,
+    Play = 1,
+    Pause = 2,
+    UpdateTime = 3,
+    SetTime = 4,
+    SetMedia = 5,
+    Subtitles = 6,
+    Warning = 7,
+    /// The server is shutting down, data is when it's expected back in milliseconds since the epoch.
+    Restarting = 8,
+}
+
+impl Command {
+    pub fn parse(message: &str) -> Option<(Self, &str)> {
+        let (command, data) = message.split_once(';')?;
+        let command = match command.parse::<u8>().ok()? {
+            0 => Self::Ping,
+            1 => Self::Play,
+            2 => Self::Pause,
+            3 => Self::UpdateTime,
+            4 => Self::SetTime,
+            5 => Self::SetMedia,
+            6 => Self::Subtitles,
+            7 => Self::Warning,
+            8 => Self::Restarting,
+            _ => return None,
+        };
+        Some((command, data))
+    }
+
+    pub fn message(self, data: &str) -> String {
+        format!("{};{data}", self as u8)
+    }
+
+    /// Name used in metrics.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Ping => "ping",
+            Self::Play => "play",
+            Self::Pause => "pause",
+            Self::UpdateTime => "update_time",
+            Self::SetTime => "set_time",
+            Self::SetMedia => "set_media",
+            Self::Subtitles => "subtitles",
+            Self::Warning => "warning",
+            Self::Restarting => "restarting",
+        }
+    }
+}
 
 #[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -18,19 +96,35 @@ struct RoomClient {
     id: u32,
     user_id: u32,
     name: String,
+    guest: bool,
     #[serde(skip)]
     socket: actix_ws::Session,
+    #[serde(skip)]
+    metrics: Arc<Metrics>,
 }
 
 impl RoomClient {
     async fn send_message(&mut self, message: &str) {
-        self.socket.text(message).await.unwrap_or_else(|_| {
-            log::error!(
-                "Failed to send message to client {}: {}",
-                self.user_id,
-                message
-            );
-        });
+        match self.socket.text(message).await {
+            Ok(()) => {
+                let command =
+                    Command::parse(message).map_or("unknown", |(command, _)| command.label());
+                self.metrics
+                    .messages_relayed
+                    .with_label_values(&[command])
+                    .inc();
+                self.metrics.bytes_sent.inc_by(message.len() as u64);
+            }
+            Err(_) => {
+                self.metrics.send_failures.inc();
+                tracing::error!(
+                    ws_id = self.id,
+                    user_id = self.user_id,
+                    message,
+                    "Failed to send message"
+                );
+            }
+        }
     }
 }
 
@@ -39,7 +133,37 @@ impl RoomClient {
 pub struct Room {
     id: u32,
     name: String,
+    /// The user who created the room, the only one allowed to change who may join.
+    owner: u32,
+    allow_guests: bool,
     members: Vec<RoomClient>,
+    media: Option<String>,
+    #[serde(skip)]
+    subtitles: Vec<SubtitleTrack>,
+    #[serde(skip)]
+    subtitle_offset: i64,
+    #[serde(skip)]
+    events: EventLog,
+    /// The latest `UpdateTime` message and the socket it came from, waiting for the next tick.
+    #[serde(skip)]
+    latest_time: Option<(u32, String)>,
+    #[serde(skip)]
+    created: Instant,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SubtitleTrackInfo<'a> {
+    #[serde(flatten)]
+    track: &'a SubtitleTrack,
+    url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SubtitleList<'a> {
+    offset: i64,
+    tracks: Vec<SubtitleTrackInfo<'a>>,
 }
 
 impl PartialEq for Room {
@@ -70,10 +194,82 @@ impl Room {
         }
     }
 
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn media(&self) -> Option<&str> {
+        self.media.as_deref()
+    }
+
+    pub fn socket_count(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn has_user(&self, user_id: u32) -> bool {
+        self.members.iter().any(|member| member.user_id == user_id)
+    }
+
+    /// Closes the sockets of the members matching `kick`.
+    async fn disconnect(
+        &mut self,
+        kick: impl Fn(&RoomClient) -> bool,
+        code: CloseCode,
+        reason: &str,
+    ) {
+        let (kicked, members): (Vec<RoomClient>, Vec<RoomClient>) =
+            std::mem::take(&mut self.members)
+                .into_iter()
+                .partition(|member| kick(member));
+        self.members = members;
+        for member in kicked {
+            self.events
+                .push(Some(member.user_id), EventKind::Leave { ws_id: member.id });
+            let reason = CloseReason::from((code, reason));
+            let _ = member.socket.close(Some(reason)).await;
+        }
+    }
+
+    fn check_access(&self, user: &SessionUser) -> Result<(), Error> {
+        match user.guest && !self.allow_guests {
+            true => Err(forbidden(
+                "guests_not_allowed",
+                "Guests are not allowed in this room",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Subtitle tracks uploaded for the room's current media.
+    fn subtitle_list(&self) -> SubtitleList<'_> {
+        SubtitleList {
+            offset: self.subtitle_offset,
+            tracks: self
+                .subtitles
+                .iter()
+                .filter(|track| track.media == self.media)
+                .map(|track| SubtitleTrackInfo {
+                    track,
+                    url: format!(
+                        "/api/rooms/{}/subtitles/{}?offset={}",
+                        self.id, track.id, self.subtitle_offset
+                    ),
+                })
+                .collect(),
+        }
+    }
+
+    fn subtitles_message(&self) -> String {
+        Command::Subtitles.message(&serde_json::to_string(&self.subtitle_list()).unwrap())
+    }
+
+    /// Records the `Leave` unless the member was already disconnected, which recorded it.
     #[must_use = "this `bool` must be used to delete the room if it's empty"]
     async fn remove_member(&mut self, id: u32) -> bool {
         if let Some(index) = self.members.iter().position(|member| member.id == id) {
             let member = self.members.remove(index);
+            self.events
+                .push(Some(member.user_id), EventKind::Leave { ws_id: member.id });
             let _ = member.socket.close(None).await;
         }
         self.members.is_empty()
@@ -82,52 +278,137 @@ impl Room {
 
 #[get("/api/rooms/{id}/ws")]
 async fn connect(
+    data: web::Data<AppData>,
     req: HttpRequest,
     body: web::Payload,
     id: web::Path<u32>,
-    session: Session,
+    user: SessionUser,
 ) -> Result<impl Responder, Error> {
-    let user = SessionUser::try_from(&session)?;
+    user.require(Scope::RoomsControl)?;
+    if data.shutting_down.load(Ordering::Relaxed) {
+        return Err(service_unavailable("restarting", "Server is restarting"));
+    }
     let room_id = id.into_inner();
-    let (res, mut socket, mut stream) = actix_ws::handle(&req, body)?;
+    let (res, mut socket, stream) = actix_ws::handle(&req, body)?;
+    let mut stream = stream.max_frame_size(data.rate_limits.max_frame_size);
 
-    let rooms_guard = AppData::get().rooms.read().await;
-    let room = rooms_guard
-        .get(&room_id)
-        .ok_or_else(|| ErrorNotFound(format!("Room with id {room_id} not found")))?;
+    let rooms_guard = data.rooms.read().await;
+    let room = rooms_guard.get(&room_id).ok_or_else(|| {
+        not_found(
+            "room_not_found",
+            format!("Room with id {room_id} not found"),
+        )
+    })?;
 
     let ws_id = SOCKET_ID_INCREMENT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     {
         let mut room = room.write().await;
-        room.members.push(RoomClient {
+        room.check_access(&user)?;
+        let mut client = RoomClient {
             id: ws_id,
             user_id: user.id,
-            name: user.name,
+            name: data.preferences.display_name(&user),
+            guest: user.guest,
             socket: socket.clone(),
-        });
+            metrics: data.metrics.clone(),
+        };
+        if let (Some(path), Some(library)) = (&room.media, &data.media) {
+            let media = library.sign(room_id, user.id, path);
+            client
+                .send_message(&Command::SetMedia.message(&media.url))
+                .await;
+        }
+        if !room.subtitle_list().tracks.is_empty() {
+            client.send_message(&room.subtitles_message()).await;
+        }
+        room.events.push(
+            Some(user.id),
+            EventKind::Join {
+                ws_id,
+                name: client.name.clone(),
+            },
+        );
+        room.members.push(client);
     }
+    drop(rooms_guard);
 
-    rt::spawn(async move {
+    let user_id = user.id;
+    // outlives the request's span, so the request id is carried over
+    let request_id = RequestId::of(&req);
+    let span = tracing::info_span!("socket", room_id, ws_id, user_id, request_id);
+    span.in_scope(|| tracing::debug!("Joined room"));
+    let mut limiter = SocketLimiter::new(&data.rate_limits);
+    let task = async move {
+        let mut close_reason = None;
         loop {
             let msg = tokio::select! {
                 _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => break,
                 msg = stream.next() => match msg {
                     Some(Ok(msg)) => msg,
+                    Some(Err(ProtocolError::Overflow)) => {
+                        close_reason = Some(CloseReason::from((CloseCode::Size, "Frame too large")));
+                        break;
+                    }
                     _ => break,
                 }
             };
             match msg {
                 Message::Text(text) => {
-                    // ping
-                    if text.starts_with("0;") {
-                        continue;
+                    data.metrics.bytes_received.inc_by(text.len() as u64);
+                    let parsed = Command::parse(&text);
+                    match limiter.check(parsed.map(|(command, _)| command)) {
+                        Verdict::Allow => (),
+                        Verdict::Drop => continue,
+                        Verdict::Warn => {
+                            let warning = Command::Warning.message("Rate limit exceeded");
+                            if socket.text(warning).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        Verdict::Disconnect => {
+                            tracing::warn!("Disconnecting socket for flooding the room");
+                            close_reason = Some(CloseReason::from((
+                                CloseCode::Policy,
+                                "Rate limit exceeded",
+                            )));
+                            break;
+                        }
                     }
-                    let rooms_guard = AppData::get().rooms.read().await;
+                    let (command, payload) = match parsed {
+                        None | Some((Command::Ping, _)) => continue,
+                        // only the server may announce media, subtitles, warnings and restarts
+                        Some((
+                            Command::SetMedia
+                            | Command::Subtitles
+                            | Command::Warning
+                            | Command::Restarting,
+                            _,
+                        )) => continue,
+                        Some(command) => command,
+                    };
+                    let rooms_guard = data.rooms.read().await;
                     let room = match rooms_guard.get(&room_id) {
                         Some(room) => room,
                         None => break,
                     };
                     let mut room = room.write().await;
+                    if command == Command::UpdateTime {
+                        room.latest_time = Some((ws_id, text.to_string()));
+                        continue;
+                    }
+                    // a pending time update would undo a pause or seek
+                    room.latest_time = None;
+
+                    let time = payload.parse().ok();
+                    match command {
+                        Command::Play => room.events.push(Some(user_id), EventKind::Play { time }),
+                        Command::Pause => room.events.push(Some(user_id), EventKind::Pause),
+                        Command::SetTime => {
+                            room.events.push(Some(user_id), EventKind::Seek { time })
+                        }
+                        _ => (),
+                    }
                     room.send_message(&text, Some(ws_id)).await;
                 }
                 Message::Ping(msg) => {
@@ -142,28 +423,131 @@ async fn connect(
             }
         }
 
-        let rooms_guard = AppData::get().rooms.read().await;
+        tracing::debug!(?close_reason, "Left room");
+        if close_reason.is_some() {
+            let _ = socket.close(close_reason).await;
+        }
+
+        let rooms_guard = data.rooms.read().await;
         if let Some(room) = rooms_guard.get(&room_id) {
-            if room.write().await.remove_member(ws_id).await {
+            let mut room = room.write().await;
+            if room.remove_member(ws_id).await {
+                drop(room);
                 drop(rooms_guard);
-                AppData::get().rooms.write().await.remove(&room_id);
+                if let Some(room) = data.rooms.write().await.remove(&room_id) {
+                    let lifetime = room.into_inner().created.elapsed();
+                    tracing::info!(?lifetime, "Closed empty room");
+                    data.metrics.room_lifetime.observe(lifetime.as_secs_f64());
+                }
             }
         }
-    });
+    };
+    rt::spawn(task.instrument(span));
 
     Ok(res)
 }
 
+/// Time updates are state rather than events, so only the latest one is relayed each tick.
+async fn broadcast_time_updates(data: web::Data<AppData>, room_id: u32) {
+    let mut interval = tokio::time::interval(data.time_update_tick);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        let rooms_guard = data.rooms.read().await;
+        let Some(room) = rooms_guard.get(&room_id) else {
+            break;
+        };
+        let mut room = room.write().await;
+        if let Some((author, message)) = room.latest_time.take() {
+            room.send_message(&message, Some(author)).await;
+        }
+    }
+}
+
+/// Disconnects `user_id` from every room.
+pub async fn disconnect_user(data: &AppData, user_id: u32, reason: &str) {
+    let rooms_guard = data.rooms.read().await;
+    for room in rooms_guard.values() {
+        room.write()
+            .await
+            .disconnect(
+                |member| member.user_id == user_id,
+                CloseCode::Policy,
+                reason,
+            )
+            .await;
+    }
+}
+
+/// Returns `false` if there is no such socket.
+pub async fn disconnect_socket(data: &AppData, room_id: u32, ws_id: u32) -> bool {
+    let rooms_guard = data.rooms.read().await;
+    let Some(room) = rooms_guard.get(&room_id) else {
+        return false;
+    };
+    let mut room = room.write().await;
+    if !room.members.iter().any(|member| member.id == ws_id) {
+        return false;
+    }
+    room.disconnect(
+        |member| member.id == ws_id,
+        CloseCode::Policy,
+        "Disconnected by an admin",
+    )
+    .await;
+    true
+}
+
+/// Removes a room and disconnects its members, returns `false` if it doesn't exist.
+pub async fn delete(data: &AppData, room_id: u32) -> bool {
+    let room = data.rooms.write().await.remove(&room_id);
+    match room {
+        Some(room) => {
+            let mut room = room.into_inner();
+            room.disconnect(|_| true, CloseCode::Policy, "Room was deleted")
+                .await;
+            data.metrics
+                .room_lifetime
+                .observe(room.created.elapsed().as_secs_f64());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stops new joins, tells every member when to expect the server back and closes their sockets.
+pub async fn shutdown(data: &AppData, restart_delay: Duration) {
+    data.shutting_down.store(true, Ordering::Relaxed);
+    let back_at = timestamp() + restart_delay.as_millis() as u64;
+    let message = Command::Restarting.message(&back_at.to_string());
+    let rooms_guard = data.rooms.read().await;
+    for room in rooms_guard.values() {
+        let mut room = room.write().await;
+        room.send_message(&message, None).await;
+        room.disconnect(|_| true, CloseCode::Away, "Server restarting")
+            .await;
+    }
+}
+
 #[get("/api/rooms/{id}")]
-async fn get(session: Session, id: web::Path<u32>) -> Result<impl Responder, Error> {
-    SessionUser::try_from(&session)?;
+async fn get(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
     let id = id.into_inner();
-    
-    let rooms_guard = AppData::get().rooms.read().await;
+
+    let rooms_guard = data.rooms.read().await;
     if let Some(room) = rooms_guard.get(&id) {
-        return Ok(web::Json(room.read().await.clone()));
+        let room = room.read().await;
+        room.check_access(&user)?;
+        return Ok(HttpResponse::Ok().json(&*room));
     }
-    Err(ErrorNotFound(format!("Room with id {id} not found")))
+    Err(not_found(
+        "room_not_found",
+        format!("Room with id {id} not found"),
+    ))
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -172,33 +556,430 @@ struct NewRoom {
 }
 
 #[post("/api/rooms")]
-async fn new(session: Session, new_room: web::Json<NewRoom>) -> Result<impl Responder, Error> {
-    SessionUser::try_from(&session)?;
+async fn new(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    new_room: web::Json<NewRoom>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsWrite)?;
+    if user.guest {
+        return Err(forbidden("guests_not_allowed", "Guests can't create rooms"));
+    }
+    if data.shutting_down.load(Ordering::Relaxed) {
+        return Err(service_unavailable("restarting", "Server is restarting"));
+    }
+    if !data.room_creation_limiter.try_take(user.id) {
+        return Err(too_many_requests(
+            "rate_limited",
+            "Too many rooms created, try again later",
+        ));
+    }
 
     let id = ROOM_ID_INCREMENT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    let new_room = Room {
+    let mut new_room = Room {
         id,
         name: new_room.name.clone(),
+        owner: user.id,
+        allow_guests: false,
         members: Vec::new(),
+        media: None,
+        subtitles: Vec::new(),
+        subtitle_offset: 0,
+        events: EventLog::default(),
+        latest_time: None,
+        created: Instant::now(),
     };
+    new_room.events.push(
+        Some(user.id),
+        EventKind::Created {
+            name: new_room.name.clone(),
+        },
+    );
 
-    let mut rooms_guard = AppData::get().rooms.write().await;
+    let mut rooms_guard = data.rooms.write().await;
     rooms_guard.insert(id, RwLock::new(new_room.clone()));
+    tracing::info!(room_id = id, user_id = user.id, "Created room");
+    rt::spawn(
+        broadcast_time_updates(data.clone(), id)
+            .instrument(tracing::info_span!("room", room_id = id)),
+    );
 
     Ok(web::Json(new_room))
 }
 
 #[get("/api/rooms")]
-async fn list(session: Session) -> Result<impl Responder, Error> {
-    SessionUser::try_from(&session)?;
+async fn list(data: web::Data<AppData>, user: SessionUser) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
 
-    let rooms_guard = AppData::get().rooms.read().await;
-    let mut rooms: Vec<Room> = Vec::with_capacity(rooms_guard.len());
+    let rooms_guard = data.rooms.read().await;
+    let mut rooms = Vec::with_capacity(rooms_guard.len());
     for room in rooms_guard.values() {
-        rooms.push(room.read().await.clone());
+        let room = room.read().await;
+        if room.check_access(&user).is_ok() {
+            rooms.push(room);
+        }
+    }
+    rooms.sort_by_key(|room| room.id);
+    Ok(HttpResponse::Ok().json(rooms.iter().map(|room| &**room).collect::<Vec<&Room>>()))
+}
+
+#[derive(Deserialize)]
+struct SetMedia {
+    path: Option<String>,
+}
+
+#[put("/api/rooms/{id}/media")]
+async fn set_media(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+    body: web::Json<SetMedia>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsControl)?;
+    let library = media::library(&data)?;
+    let id = id.into_inner();
+
+    if let Some(path) = &body.path {
+        library
+            .resolve(path)
+            .ok_or_else(|| not_found("media_not_found", format!("Media {path:?} not found")))?;
+    }
+
+    let rooms_guard = data.rooms.read().await;
+    let mut room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .write()
+        .await;
+    room.check_access(&user)?;
+    if !room.has_user(user.id) {
+        return Err(forbidden("not_room_member", "Not a member of this room"));
+    }
+
+    room.media = body.into_inner().path;
+    let path = room.media.clone();
+    room.events
+        .push(Some(user.id), EventKind::MediaChanged { path });
+    if let Some(path) = room.media.clone() {
+        for member in &mut room.members {
+            let media = library.sign(id, member.user_id, &path);
+            member
+                .send_message(&Command::SetMedia.message(&media.url))
+                .await;
+        }
+    }
+    let message = room.subtitles_message();
+    room.send_message(&message, None).await;
+    Ok(HttpResponse::Ok().json(&*room))
+}
+
+#[get("/api/rooms/{id}/media")]
+async fn get_media(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
+    let library = media::library(&data)?;
+    let id = id.into_inner();
+
+    let rooms_guard = data.rooms.read().await;
+    let room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .read()
+        .await;
+    room.check_access(&user)?;
+    if !room.has_user(user.id) {
+        return Err(forbidden("not_room_member", "Not a member of this room"));
+    }
+    match &room.media {
+        Some(path) => Ok(web::Json(library.sign(id, user.id, path))),
+        None => Err(not_found("no_media", "No media set for this room")),
+    }
+}
+
+#[derive(Deserialize)]
+struct GuestAccess {
+    allow: bool,
+}
+
+#[put("/api/rooms/{id}/guests")]
+async fn set_guest_access(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+    body: web::Json<GuestAccess>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsWrite)?;
+    let id = id.into_inner();
+
+    let rooms_guard = data.rooms.read().await;
+    let mut room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .write()
+        .await;
+    room.check_access(&user)?;
+    if room.owner != user.id {
+        return Err(forbidden(
+            "owner_only",
+            "Only the owner can change guest access",
+        ));
+    }
+
+    room.allow_guests = body.allow;
+    room.events.push(
+        Some(user.id),
+        EventKind::GuestAccess {
+            allowed: body.allow,
+        },
+    );
+    if !body.allow {
+        room.disconnect(
+            |member| member.guest,
+            CloseCode::Policy,
+            "Guest access was disabled",
+        )
+        .await;
+    }
+    Ok(HttpResponse::Ok().json(&*room))
+}
+
+#[derive(Deserialize)]
+struct NewSubtitleTrack {
+    label: Option<String>,
+    language: Option<String>,
+    format: Option<subtitles::Format>,
+}
+
+#[post("/api/rooms/{id}/subtitles")]
+async fn upload_subtitles(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+    query: web::Query<NewSubtitleTrack>,
+    body: web::Bytes,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsWrite)?;
+    if !data.subtitle_upload_limiter.try_take(user.id) {
+        return Err(too_many_requests(
+            "rate_limited",
+            "Too many subtitles uploaded, try again later",
+        ));
+    }
+    let id = id.into_inner();
+    let query = query.into_inner();
+
+    let input = std::str::from_utf8(&body)
+        .map_err(|_| bad_request("invalid_subtitles", "Subtitle files must be UTF-8 encoded"))?;
+    let cues = subtitles::parse(input, query.format)
+        .map_err(|err| bad_request("invalid_subtitles", err))?;
+
+    let rooms_guard = data.rooms.read().await;
+    let mut room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .write()
+        .await;
+    room.check_access(&user)?;
+    if !room.has_user(user.id) {
+        return Err(forbidden("not_room_member", "Not a member of this room"));
+    }
+
+    if room.subtitles.len() >= MAX_SUBTITLE_TRACKS {
+        // tracks for earlier media aren't shown anymore, so they make room first
+        let media = room.media.clone();
+        match room.subtitles.iter().position(|track| track.media != media) {
+            Some(index) => {
+                room.subtitles.remove(index);
+            }
+            None => {
+                return Err(conflict(
+                    "too_many_subtitles",
+                    format!("Rooms can't have more than {MAX_SUBTITLE_TRACKS} subtitle tracks"),
+                ))
+            }
+        }
+    }
+
+    let track_id = SUBTITLE_ID_INCREMENT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let label = query
+        .label
+        .unwrap_or_else(|| format!("Track {}", room.subtitle_list().tracks.len() + 1));
+    let track = SubtitleTrack {
+        id: track_id,
+        label,
+        language: query.language,
+        media: room.media.clone(),
+        cues: Arc::new(cues),
+    };
+    room.subtitles.push(track.clone());
+    room.events.push(
+        Some(user.id),
+        EventKind::SubtitleAdded {
+            track_id,
+            label: track.label.clone(),
+        },
+    );
+
+    let message = room.subtitles_message();
+    room.send_message(&message, None).await;
+    Ok(web::Json(track))
+}
+
+#[get("/api/rooms/{id}/subtitles")]
+async fn list_subtitles(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
+    let id = id.into_inner();
+
+    let rooms_guard = data.rooms.read().await;
+    let room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .read()
+        .await;
+    room.check_access(&user)?;
+    Ok(HttpResponse::Ok().json(room.subtitle_list()))
+}
+
+#[get("/api/rooms/{id}/subtitles/{track}")]
+async fn get_subtitles(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    path: web::Path<(u32, u32)>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
+    let (id, track_id) = path.into_inner();
+
+    let rooms_guard = data.rooms.read().await;
+    let room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .read()
+        .await;
+    room.check_access(&user)?;
+    let track = room
+        .subtitles
+        .iter()
+        .find(|track| track.id == track_id)
+        .ok_or_else(|| {
+            not_found(
+                "subtitle_not_found",
+                format!("Subtitle track with id {track_id} not found"),
+            )
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/vtt; charset=utf-8")
+        .body(subtitles::to_webvtt(&track.cues, room.subtitle_offset)))
+}
+
+#[derive(Deserialize)]
+struct SubtitleOffset {
+    /// Offset in milliseconds, positive values show subtitles later. Clamped to a day.
+    offset: i64,
+}
+
+#[put("/api/rooms/{id}/subtitles/offset")]
+async fn set_subtitle_offset(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+    body: web::Json<SubtitleOffset>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsWrite)?;
+    let id = id.into_inner();
+
+    let rooms_guard = data.rooms.read().await;
+    let mut room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .write()
+        .await;
+    room.check_access(&user)?;
+    if !room.has_user(user.id) {
+        return Err(forbidden("not_room_member", "Not a member of this room"));
     }
-    rooms.sort();
-    Ok(web::Json(rooms))
+
+    let offset = body
+        .offset
+        .clamp(-subtitles::MAX_OFFSET, subtitles::MAX_OFFSET);
+    room.subtitle_offset = offset;
+    room.events
+        .push(Some(user.id), EventKind::SubtitleOffset { offset });
+    let message = room.subtitles_message();
+    room.send_message(&message, None).await;
+    Ok(HttpResponse::Ok().json(room.subtitle_list()))
+}
+
+#[derive(Deserialize)]
+struct EventPage {
+    #[serde(default)]
+    after: u64,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Events<'a> {
+    events: &'a [RoomEvent],
+    /// Pass as `after` to get the next page, `None` if there are no more events.
+    next: Option<u64>,
+}
+
+#[get("/api/rooms/{id}/events")]
+async fn events(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+    query: web::Query<EventPage>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
+    let id = id.into_inner();
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    let rooms_guard = data.rooms.read().await;
+    let room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .read()
+        .await;
+    room.check_access(&user)?;
+    let events = room.events.page(query.after, limit);
+    let next = events
+        .last()
+        .map(|event| event.seq)
+        .filter(|seq| !room.events.page(*seq, 1).is_empty());
+    Ok(HttpResponse::Ok().json(Events { events, next }))
+}
+
+#[get("/api/rooms/{id}/events/export")]
+async fn export_events(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    user.require(Scope::RoomsRead)?;
+    let id = id.into_inner();
+
+    let rooms_guard = data.rooms.read().await;
+    let room = rooms_guard
+        .get(&id)
+        .ok_or_else(|| not_found("room_not_found", format!("Room with id {id} not found")))?
+        .read()
+        .await;
+    room.check_access(&user)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .append_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"room-{id}-events.jsonl\""),
+        ))
+        .body(room.events.to_json_lines()))
 }
 
 pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
@@ -206,4 +987,13 @@ pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(new);
     cfg.service(list);
     cfg.service(get);
+    cfg.service(set_media);
+    cfg.service(get_media);
+    cfg.service(set_guest_access);
+    cfg.service(set_subtitle_offset);
+    cfg.service(upload_subtitles);
+    cfg.service(list_subtitles);
+    cfg.service(get_subtitles);
+    cfg.service(export_events);
+    cfg.service(events);
 }
@@ -0,0 +1,183 @@
+use {
+    crate::{
+        clock::now,
+        config::{self, Config},
+        storage,
+    },
+    actix_web::{
+        cookie::{Cookie, CookieJar, Key, SameSite},
+        dev::ServiceRequest,
+        http::header::{self, HeaderValue},
+    },
+    base64::{engine::general_purpose::STANDARD, Engine},
+    serde::{Deserialize, Serialize},
+    std::path::PathBuf,
+};
+
+/// Name of the cookie `SessionMiddleware` stores the session key in.
+pub const COOKIE_NAME: &str = "id";
+
+pub fn decode(key: &str) -> Result<Key, String> {
+    match STANDARD.decode(key.trim()) {
+        Ok(bytes) if bytes.len() >= 64 => Ok(Key::from(&bytes)),
+        _ => Err(String::from("expected at least 64 base64 encoded bytes")),
+    }
+}
+
+fn encode(key: &Key) -> String {
+    STANDARD.encode(key.master())
+}
+
+#[derive(Serialize, Deserialize)]
+struct PreviousKey {
+    key: String,
+    /// Unix time after which cookies signed with this key are no longer accepted.
+    expires: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    current: String,
+    previous: Option<PreviousKey>,
+}
+
+fn key_file(config: &Config) -> PathBuf {
+    match &config.session.key_file {
+        Some(path) => config::expand(path),
+        None => config.data_dir.join("session_key.json"),
+    }
+}
+
+/// The key session cookies are signed with, and the one it replaced during its grace period.
+#[derive(Clone)]
+pub struct SessionKeys {
+    pub current: Key,
+    /// With the unix time it's accepted until.
+    previous: Option<(Key, u64)>,
+}
+
+impl SessionKeys {
+    /// Uses `session.key` and `session.key_previous` if set, otherwise the key file,
+    /// which is created with a new key on first run.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let path = key_file(config);
+        let config = &config.session;
+        if let Some(key) = &config.key {
+            return Ok(Self {
+                current: decode(key.expose()).expect("Invalid session.key"),
+                previous: config.key_previous.as_ref().map(|key| {
+                    (
+                        decode(key.expose()).expect("Invalid session.key_previous"),
+                        config
+                            .key_previous_expires
+                            .expect("session.key_previous_expires is required"),
+                    )
+                }),
+            });
+        }
+
+        let Some(file) = storage::load::<Option<KeyFile>>(&path)? else {
+            let current = Key::generate();
+            let file = KeyFile {
+                current: encode(&current),
+                previous: None,
+            };
+            storage::save(&path, &file)
+                .map_err(|err| format!("Unable to save session key to {path:?}: {err}"))?;
+            tracing::info!(path = %path.display(), "Generated a new session key");
+            return Ok(Self {
+                current,
+                previous: None,
+            });
+        };
+        let previous = match file.previous.filter(|previous| previous.expires > now()) {
+            Some(previous) => Some((
+                decode(&previous.key)
+                    .map_err(|err| format!("Invalid previous session key in {path:?}: {err}"))?,
+                previous.expires,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            current: decode(&file.current)
+                .map_err(|err| format!("Invalid session key in {path:?}: {err}"))?,
+            previous,
+        })
+    }
+
+    /// Replaces `req`'s session cookie if it was signed with the previous key,
+    /// returning the re-signed cookie so the client can be sent it too.
+    pub fn upgrade_cookie(&self, req: &mut ServiceRequest) -> Option<Cookie<'static>> {
+        // the server may have been running since before the grace period ended
+        let (previous, _) = self
+            .previous
+            .as_ref()
+            .filter(|(_, expires)| now() < *expires)?;
+        // parsed by hand, `req.cookies()` would cache the cookies before they're replaced
+        let mut cookies: Vec<Cookie<'static>> = req
+            .headers()
+            .get_all(header::COOKIE)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| Cookie::parse_encoded(cookie.trim().to_owned()).ok())
+            .collect();
+        let cookie = cookies.iter_mut().find(|x| x.name() == COOKIE_NAME)?;
+
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie.clone());
+        if jar.private(&self.current).get(COOKIE_NAME).is_some() {
+            return None;
+        }
+        let value = jar.private(previous).get(COOKIE_NAME)?.value().to_owned();
+
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.current)
+            .add(Cookie::new(COOKIE_NAME, value));
+        let upgraded = jar.get(COOKIE_NAME)?.clone();
+        cookie.set_value(upgraded.value().to_owned());
+
+        let header = cookies
+            .iter()
+            .map(|cookie| cookie.encoded().stripped().to_string())
+            .collect::<Vec<String>>()
+            .join("; ");
+        let headers = req.headers_mut();
+        headers.remove(header::COOKIE);
+        headers.insert(header::COOKIE, HeaderValue::from_str(&header).ok()?);
+
+        Some(
+            Cookie::build(COOKIE_NAME, upgraded.value().to_owned())
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .secure(cfg!(not(debug_assertions)))
+                .finish(),
+        )
+    }
+}
+
+/// `sync-play rotate-session-key`, the previous key stays valid for `session.key_grace` seconds.
+pub fn rotate_command(config: &Config) -> std::io::Result<()> {
+    if config.session.key.is_some() {
+        eprintln!(
+            "session.key is set, rotate it by moving it to session.key_previous \
+             and setting session.key_previous_expires instead"
+        );
+        std::process::exit(1);
+    }
+
+    let path = key_file(config);
+    let previous = storage::load::<Option<KeyFile>>(&path)
+        .map_err(std::io::Error::other)?
+        .map(|file| PreviousKey {
+            key: file.current,
+            expires: now() + config.session.key_grace,
+        });
+    let file = KeyFile {
+        current: encode(&Key::generate()),
+        previous,
+    };
+    storage::save(&path, &file)?;
+    eprintln!("Rotated the session key in {path:?}, restart the server to use it");
+    Ok(())
+}
@@ -0,0 +1,564 @@
+use {
+    crate::{admin, clock::now, error::not_found, storage, user::SessionUser, AppData},
+    actix_session::{
+        storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError},
+        Session,
+    },
+    actix_web::{cookie::time::Duration, delete, get, rt, web, Error, HttpResponse, Responder},
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    rand::{distributions::Alphanumeric, Rng},
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+const FILE: &str = "sessions.json";
+/// Session state key holding the session's public id, set by the store.
+const ID_KEY: &str = "session_id";
+/// Changes are written together, at most once per this delay.
+const WRITE_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+/// Seconds until sessions without a user expire. They only hold state for logging in, like
+/// the OIDC state or the page to return to, and can be created without logging in.
+const ANONYMOUS_TTL: u64 = 60 * 60;
+/// Sessions without a user kept at most, the least recently used ones are dropped first.
+const MAX_ANONYMOUS: usize = 10_000;
+
+/// Session keys are only stored hashed, so a leaked sessions file can't be used to log in.
+fn hash(key: &SessionKey) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_ref().as_bytes()))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct SessionRecord {
+    id: String,
+    user_id: Option<u32>,
+    created: u64,
+    last_seen: u64,
+    expires: u64,
+    state: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SessionsFile {
+    /// Keyed by the hash of the session key.
+    sessions: HashMap<String, SessionRecord>,
+}
+
+/// What users and admins get to see of a session.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    id: String,
+    user_id: Option<u32>,
+    created: u64,
+    last_seen: u64,
+    expires: u64,
+    current: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    Memory,
+    /// Saved to `sessions.json` in the data directory, so sessions survive restarts.
+    File,
+}
+
+struct Inner {
+    kind: StoreKind,
+    path: PathBuf,
+    file: Mutex<SessionsFile>,
+    /// A write is scheduled and hasn't taken its snapshot yet.
+    write_pending: AtomicBool,
+    /// Held while writing, so writes don't overlap.
+    writing: Mutex<()>,
+}
+
+/// Keeps session state on the server, the cookie only holds the session key.
+///
+/// Only sessions with a user are saved, sessions without one are kept in memory.
+#[derive(Clone)]
+pub struct ServerSessionStore(Arc<Inner>);
+
+impl ServerSessionStore {
+    pub fn new(kind: StoreKind, data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(FILE);
+        let file = match kind {
+            StoreKind::Memory => SessionsFile::default(),
+            StoreKind::File => {
+                let mut file: SessionsFile = storage::load(&path)?;
+                let now = now();
+                file.sessions
+                    .retain(|_, record| record.expires > now && record.user_id.is_some());
+                file
+            }
+        };
+        Ok(Self(Arc::new(Inner {
+            kind,
+            path,
+            file: Mutex::new(file),
+            write_pending: AtomicBool::new(false),
+            writing: Mutex::new(()),
+        })))
+    }
+
+    /// Schedules saving the sessions, unless a write is already scheduled.
+    fn persist(&self) {
+        if self.0.kind == StoreKind::Memory || self.0.write_pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let store = self.clone();
+        rt::spawn(async move {
+            rt::time::sleep(WRITE_DELAY).await;
+            match web::block(move || store.write()).await {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => tracing::error!(%err, "Unable to save sessions"),
+                Err(err) => tracing::error!(%err, "Unable to save sessions"),
+            }
+        });
+    }
+
+    /// Saves the sessions with a user. Blocks on the file system.
+    fn write(&self) -> std::io::Result<()> {
+        let _writing = self.0.writing.lock().unwrap();
+        // changes from here on schedule another write
+        self.0.write_pending.store(false, Ordering::Release);
+        let file = SessionsFile {
+            sessions: self
+                .0
+                .file
+                .lock()
+                .unwrap()
+                .sessions
+                .iter()
+                .filter(|(_, record)| record.user_id.is_some())
+                .map(|(hash, record)| (hash.clone(), record.clone()))
+                .collect(),
+        };
+        storage::save(&self.0.path, &file)
+    }
+
+    /// Saves changes that are still waiting for their write, for shutting down.
+    pub fn flush(&self) -> std::io::Result<()> {
+        match self.0.kind {
+            StoreKind::Memory => Ok(()),
+            StoreKind::File => self.write(),
+        }
+    }
+
+    fn insert(
+        &self,
+        key: &SessionKey,
+        mut state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> anyhow::Result<()> {
+        let now = now();
+        let mut file = self.0.file.lock().unwrap();
+        file.sessions.retain(|_, record| record.expires > now);
+
+        let hash = hash(key);
+        let previous = file.sessions.get(&hash);
+        let (id, created) = match previous {
+            Some(record) => (record.id.clone(), record.created),
+            None => (URL_SAFE_NO_PAD.encode(rand::random::<[u8; 12]>()), now),
+        };
+        let had_user = previous.is_some_and(|record| record.user_id.is_some());
+        state.insert(ID_KEY.to_owned(), serde_json::to_string(&id)?);
+        let user_id = state
+            .get("user")
+            .and_then(|user| serde_json::from_str::<SessionUser>(user).ok())
+            .map(|user| user.id);
+
+        let expires = expiry(now, ttl, user_id);
+        if user_id.is_none() && previous.is_none() {
+            evict_anonymous(&mut file.sessions, MAX_ANONYMOUS - 1);
+        }
+        file.sessions.insert(
+            hash,
+            SessionRecord {
+                id,
+                user_id,
+                created,
+                last_seen: now,
+                expires,
+                state,
+            },
+        );
+        drop(file);
+        if user_id.is_some() || had_user {
+            self.persist();
+        }
+        Ok(())
+    }
+
+    /// Sessions of `user_id`, or of everyone if `None`.
+    pub fn list(&self, user_id: Option<u32>, current: Option<&str>) -> Vec<SessionInfo> {
+        let now = now();
+        let file = self.0.file.lock().unwrap();
+        let mut sessions: Vec<SessionInfo> = file
+            .sessions
+            .values()
+            .filter(|record| record.expires > now)
+            .filter(|record| user_id.is_none() || record.user_id == user_id)
+            .map(|record| SessionInfo {
+                id: record.id.clone(),
+                user_id: record.user_id,
+                created: record.created,
+                last_seen: record.last_seen,
+                expires: record.expires,
+                current: Some(&*record.id) == current,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.created);
+        sessions
+    }
+
+    /// Whether `user_id` has a session, even an expired one that wasn't removed yet.
+    pub fn has_user(&self, user_id: u32) -> bool {
+        let file = self.0.file.lock().unwrap();
+        file.sessions
+            .values()
+            .any(|record| record.user_id == Some(user_id))
+    }
+
+    /// Revokes the sessions matching `filter`, returning how many there were.
+    fn revoke(&self, filter: impl Fn(&SessionRecord) -> bool) -> usize {
+        let mut file = self.0.file.lock().unwrap();
+        let count = file.sessions.len();
+        file.sessions.retain(|_, record| !filter(record));
+        let count = count - file.sessions.len();
+        drop(file);
+        if count > 0 {
+            self.persist();
+        }
+        count
+    }
+
+    pub fn revoke_session(&self, id: &str, user_id: Option<u32>) -> bool {
+        self.revoke(|record| record.id == id && (user_id.is_none() || record.user_id == user_id))
+            > 0
+    }
+
+    pub fn revoke_user(&self, user_id: u32) -> usize {
+        self.revoke(|record| record.user_id == Some(user_id))
+    }
+}
+
+/// Unix time at which a session saved `now` expires.
+fn expiry(now: u64, ttl: &Duration, user_id: Option<u32>) -> u64 {
+    let ttl = ttl.whole_seconds().max(0) as u64;
+    match user_id {
+        Some(_) => now + ttl,
+        None => now + ttl.min(ANONYMOUS_TTL),
+    }
+}
+
+/// Drops the least recently used sessions without a user until at most `max` are left.
+fn evict_anonymous(sessions: &mut HashMap<String, SessionRecord>, max: usize) {
+    let count = sessions
+        .values()
+        .filter(|record| record.user_id.is_none())
+        .count();
+    if count <= max {
+        return;
+    }
+    let mut anonymous: Vec<(u64, String)> = sessions
+        .iter()
+        .filter(|(_, record)| record.user_id.is_none())
+        .map(|(hash, record)| (record.last_seen, hash.clone()))
+        .collect();
+    anonymous.sort_unstable();
+    for (_, hash) in &anonymous[..count - max] {
+        sessions.remove(hash);
+    }
+}
+
+impl SessionStore for ServerSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let now = now();
+        let mut file = self.0.file.lock().unwrap();
+        match file.sessions.get_mut(&hash(session_key)) {
+            Some(record) if record.expires > now => {
+                record.last_seen = now;
+                Ok(Some(record.state.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let key: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect();
+        let key = SessionKey::try_from(key).map_err(|err| SaveError::Other(err.into()))?;
+        self.insert(&key, session_state, ttl)
+            .map_err(SaveError::Other)?;
+        Ok(key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let exists = self
+            .0
+            .file
+            .lock()
+            .unwrap()
+            .sessions
+            .contains_key(&hash(&session_key));
+        // revoked while handling the request, start over instead of bringing it back
+        if !exists {
+            return self
+                .save(HashMap::new(), ttl)
+                .await
+                .map_err(|err| UpdateError::Other(err.into()));
+        }
+        self.insert(&session_key, session_state, ttl)
+            .map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        let mut file = self.0.file.lock().unwrap();
+        let user_id = match file.sessions.get_mut(&hash(session_key)) {
+            Some(record) => {
+                record.expires = expiry(now(), ttl, record.user_id);
+                record.user_id
+            }
+            None => None,
+        };
+        drop(file);
+        if user_id.is_some() {
+            self.persist();
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        let removed = self
+            .0
+            .file
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(&hash(session_key));
+        if removed.is_some_and(|record| record.user_id.is_some()) {
+            self.persist();
+        }
+        Ok(())
+    }
+}
+
+fn current_id(session: &Session) -> Option<String> {
+    session.get::<String>(ID_KEY).ok().flatten()
+}
+
+#[get("/auth/sessions")]
+async fn list(data: web::Data<AppData>, session: Session) -> Result<impl Responder, Error> {
+    let user = SessionUser::from_session(&session, &data)?;
+    let sessions = &data.sessions;
+    Ok(web::Json(
+        sessions.list(Some(user.id), current_id(&session).as_deref()),
+    ))
+}
+
+/// Logs out everywhere, including this session.
+#[delete("/auth/sessions")]
+async fn revoke_all(data: web::Data<AppData>, session: Session) -> Result<impl Responder, Error> {
+    let user = SessionUser::from_session(&session, &data)?;
+    data.sessions.revoke_user(user.id);
+    session.purge();
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/auth/sessions/{id}")]
+async fn revoke(
+    data: web::Data<AppData>,
+    session: Session,
+    id: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let user = SessionUser::from_session(&session, &data)?;
+    match data.sessions.revoke_session(&id, Some(user.id)) {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(not_found("session_not_found", "Session not found")),
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionFilter {
+    user: Option<u32>,
+}
+
+#[get("/api/admin/sessions")]
+async fn admin_list(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    session: Session,
+    query: web::Query<SessionFilter>,
+) -> Result<impl Responder, Error> {
+    admin::require_admin(&data, &user)?;
+    let sessions = &data.sessions;
+    Ok(web::Json(
+        sessions.list(query.user, current_id(&session).as_deref()),
+    ))
+}
+
+#[delete("/api/admin/sessions/{id}")]
+async fn admin_revoke(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    admin::require_admin(&data, &user)?;
+    match data.sessions.revoke_session(&id, None) {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(not_found("session_not_found", "Session not found")),
+    }
+}
+
+#[delete("/api/admin/users/{id}/sessions")]
+async fn admin_revoke_user(
+    data: web::Data<AppData>,
+    user: SessionUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    admin::require_admin(&data, &user)?;
+    let count = data.sessions.revoke_user(id.into_inner());
+    Ok(web::Json(count))
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(revoke_all);
+    cfg.service(revoke);
+    cfg.service(admin_list);
+    cfg.service(admin_revoke);
+    cfg.service(admin_revoke_user);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> ServerSessionStore {
+        let dir =
+            std::env::temp_dir().join(format!("sync-play-sessions-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        ServerSessionStore::new(StoreKind::File, &dir).unwrap()
+    }
+
+    fn user_state(id: u32) -> HashMap<String, String> {
+        let user = SessionUser {
+            id,
+            name: String::from("Alice"),
+            guest: false,
+            scopes: None,
+        };
+        HashMap::from([(String::from("user"), serde_json::to_string(&user).unwrap())])
+    }
+
+    fn saved(store: &ServerSessionStore) -> SessionsFile {
+        storage::read(&store.0.path).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn only_saves_sessions_with_a_user() {
+        let store = store("saves");
+        let ttl = Duration::days(7);
+        let anonymous = store.save(HashMap::new(), &ttl).await.unwrap();
+        store.flush().unwrap();
+        assert!(saved(&store).sessions.is_empty());
+
+        let key = store.update(anonymous, user_state(1), &ttl).await.unwrap();
+        store.flush().unwrap();
+        let file = saved(&store);
+        assert_eq!(file.sessions.len(), 1);
+        assert_eq!(file.sessions[&hash(&key)].user_id, Some(1));
+
+        store.delete(&key).await.unwrap();
+        store.flush().unwrap();
+        assert!(saved(&store).sessions.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn batches_writes() {
+        let store = store("batches");
+        store.save(user_state(1), &Duration::days(7)).await.unwrap();
+        store.save(user_state(2), &Duration::days(7)).await.unwrap();
+        assert!(store.0.write_pending.load(Ordering::Acquire));
+        assert!(!store.0.path.exists());
+
+        rt::time::sleep(WRITE_DELAY * 2).await;
+        assert!(!store.0.write_pending.load(Ordering::Acquire));
+        assert_eq!(saved(&store).sessions.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn anonymous_sessions_expire_sooner() {
+        let store = store("expiry");
+        store
+            .save(HashMap::new(), &Duration::days(7))
+            .await
+            .unwrap();
+        store.save(user_state(1), &Duration::days(7)).await.unwrap();
+        let now = now();
+        for session in store.list(None, None) {
+            match session.user_id {
+                Some(_) => assert!(session.expires >= now + 7 * 24 * 60 * 60),
+                None => assert!(session.expires <= now + ANONYMOUS_TTL),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn knows_which_users_have_sessions() {
+        let store = store("has-user");
+        let key = store.save(user_state(1), &Duration::days(7)).await.unwrap();
+        assert!(store.has_user(1));
+        assert!(!store.has_user(2));
+        store.delete(&key).await.unwrap();
+        assert!(!store.has_user(1));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_anonymous_sessions() {
+        let record = |last_seen, user_id| SessionRecord {
+            id: String::new(),
+            user_id,
+            created: 0,
+            last_seen,
+            expires: u64::MAX,
+            state: HashMap::new(),
+        };
+        let mut sessions = HashMap::from([
+            (String::from("old"), record(1, None)),
+            (String::from("user"), record(0, Some(1))),
+            (String::from("new"), record(3, None)),
+            (String::from("newer"), record(4, None)),
+        ]);
+        evict_anonymous(&mut sessions, 2);
+        let mut left: Vec<&str> = sessions.keys().map(String::as_str).collect();
+        left.sort_unstable();
+        assert_eq!(left, ["new", "newer", "user"]);
+    }
+}
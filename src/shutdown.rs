@@ -0,0 +1,39 @@
+use {
+    crate::{config::ShutdownConfig, room, AppData},
+    actix_web::{dev::ServerHandle, rt, web},
+    std::time::Duration,
+};
+
+/// Resolves on SIGTERM or Ctrl+C.
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use rt::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => (),
+            _ = rt::signal::ctrl_c() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = rt::signal::ctrl_c().await;
+}
+
+/// Waits for a shutdown signal, then says goodbye to every room before stopping the server.
+///
+/// Everything has to be done within the configured deadline, after which the server is
+/// stopped without waiting for the remaining connections.
+pub async fn on_signal(server: ServerHandle, data: web::Data<AppData>, config: ShutdownConfig) {
+    signal().await;
+    tracing::info!("Shutting down, notifying rooms");
+
+    let deadline = Duration::from_secs(config.deadline);
+    let graceful = async {
+        room::shutdown(&data, Duration::from_secs(config.restart_delay)).await;
+        server.stop(true).await;
+    };
+    if rt::time::timeout(deadline, graceful).await.is_err() {
+        tracing::warn!(?deadline, "Shutdown took too long, stopping now");
+        server.stop(false).await;
+    }
+}
@@ -0,0 +1,40 @@
+use {
+    serde::{de::DeserializeOwned, Serialize},
+    std::{io, path::Path},
+};
+
+/// Loads a JSON file at startup, or the default value if it doesn't exist yet.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    read(path).map_err(|err| format!("Unable to load {path:?}: {err}"))
+}
+
+/// Like [`load`], for files that may change while the server is running.
+pub fn read<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Atomically replaces a JSON file, creating its directory if needed.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    write_atomic(path, &serde_json::to_vec_pretty(value)?)
+}
+
+/// Checks that files can be written to `dir`, creating it if needed.
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(".writable");
+    write_atomic(&path, b"")?;
+    std::fs::remove_file(path)
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(tmp, path)
+}
@@ -0,0 +1,352 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Srt,
+    Vtt,
+    Ass,
+}
+
+impl Format {
+    fn detect(input: &str) -> Self {
+        if input.starts_with("WEBVTT") {
+            Self::Vtt
+        } else if input.contains("[Script Info]") || input.contains("[Events]") {
+            Self::Ass
+        } else {
+            Self::Srt
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cue {
+    start: i64,
+    end: i64,
+    settings: String,
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleTrack {
+    pub id: u32,
+    pub label: String,
+    pub language: Option<String>,
+    /// The room media this track was uploaded for, `None` for local files.
+    #[serde(skip)]
+    pub media: Option<String>,
+    #[serde(skip)]
+    pub cues: Arc<Vec<Cue>>,
+}
+
+/// Subtitle offsets are limited to a day in either direction, in milliseconds.
+pub const MAX_OFFSET: i64 = 24 * 60 * 60 * 1000;
+
+/// Parses ASCII digits only, unlike `str::parse` which also takes a sign.
+fn parse_digits(input: &str) -> Option<i64> {
+    match !input.is_empty() && input.bytes().all(|byte| byte.is_ascii_digit()) {
+        true => input.parse().ok(),
+        false => None,
+    }
+}
+
+/// Parses `HH:MM:SS.mmm`, `MM:SS.mmm` and the SRT `HH:MM:SS,mmm` / ASS `H:MM:SS.cc` variants into milliseconds.
+fn parse_timestamp(input: &str) -> Option<i64> {
+    let input = input.trim().replace(',', ".");
+    let (clock, fraction) = input.split_once('.').unwrap_or((&input, "0"));
+    let millis = match fraction.len() {
+        1 => parse_digits(fraction)? * 100,
+        2 => parse_digits(fraction)? * 10,
+        3 => parse_digits(fraction)?,
+        _ => return None,
+    };
+    let mut parts = clock.split(':');
+    let mut seconds = parse_digits(parts.next()?)?;
+    for part in parts.by_ref().take(2) {
+        let part = parse_digits(part).filter(|part| *part < 60)?;
+        seconds = seconds.checked_mul(60)?.checked_add(part)?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    seconds.checked_mul(1000)?.checked_add(millis)
+}
+
+fn format_timestamp(millis: i64) -> String {
+    let millis = millis.max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Parses a `start --> end [settings]` line shared by SRT and WebVTT.
+fn parse_timing(line: &str) -> Option<(i64, i64, String)> {
+    let (start, rest) = line.split_once("-->")?;
+    let rest = rest.trim_start();
+    let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let settings = match settings.trim() {
+        "" => String::new(),
+        settings => format!(" {settings}"),
+    };
+    let (start, end) = (parse_timestamp(start)?, parse_timestamp(end)?);
+    (start <= end).then_some((start, end, settings))
+}
+
+/// Parses SRT and WebVTT, which share the same block structure.
+fn parse_blocks(input: &str) -> Result<Vec<Cue>, String> {
+    let mut cues = Vec::new();
+    for block in input.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+        let Some(first) = lines.next() else {
+            continue;
+        };
+        if first.starts_with("WEBVTT")
+            || first.starts_with("NOTE")
+            || first.starts_with("STYLE")
+            || first.starts_with("REGION")
+        {
+            continue;
+        }
+        let timing = match first.contains("-->") {
+            true => first,
+            false => lines
+                .next()
+                .ok_or_else(|| format!("Missing cue timing after {first:?}"))?,
+        };
+        let (start, end, settings) =
+            parse_timing(timing).ok_or_else(|| format!("Invalid cue timing {timing:?}"))?;
+        cues.push(Cue {
+            start,
+            end,
+            settings,
+            text: lines.collect::<Vec<_>>().join("\n"),
+        });
+    }
+    Ok(cues)
+}
+
+/// Parses the `[Events]` section of an ASS/SSA file, keeping only the plain dialogue text.
+fn parse_ass(input: &str) -> Result<Vec<Cue>, String> {
+    let mut format: Option<Vec<String>> = None;
+    let mut in_events = false;
+    let mut cues = Vec::new();
+
+    for line in input.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[Events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format = Some(fields.split(',').map(|x| x.trim().to_lowercase()).collect());
+        } else if let Some(fields) = line.strip_prefix("Dialogue:") {
+            let format = format
+                .as_ref()
+                .ok_or("Dialogue before Format line in [Events]")?;
+            let fields: Vec<&str> = fields.splitn(format.len(), ',').collect();
+            let field = |name: &str| {
+                format
+                    .iter()
+                    .position(|x| x == name)
+                    .and_then(|index| fields.get(index))
+                    .ok_or_else(|| format!("Missing {name} field in {line:?}"))
+            };
+            let start = field("start")?;
+            let end = field("end")?;
+            let start = parse_timestamp(start).ok_or_else(|| format!("Invalid time {start:?}"))?;
+            let end = parse_timestamp(end).ok_or_else(|| format!("Invalid time {end:?}"))?;
+            if end < start {
+                return Err(format!("Cue ends before it starts in {line:?}"));
+            }
+            cues.push(Cue {
+                start,
+                end,
+                settings: String::new(),
+                text: strip_ass_tags(field("text")?),
+            });
+        }
+    }
+
+    cues.sort_by_key(|cue| cue.start);
+    Ok(cues)
+}
+
+fn strip_ass_tags(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_tag = true,
+            '}' => in_tag = false,
+            c if !in_tag => output.push(c),
+            _ => (),
+        }
+    }
+    output
+        .replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+}
+
+/// Parses a subtitle file, detecting the format from its contents if `format` is `None`.
+pub fn parse(input: &str, format: Option<Format>) -> Result<Vec<Cue>, String> {
+    let input = input.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    match format.unwrap_or_else(|| Format::detect(&input)) {
+        Format::Srt | Format::Vtt => parse_blocks(&input),
+        Format::Ass => parse_ass(&input),
+    }
+}
+
+/// Renders cues as WebVTT, shifting every cue by `offset` milliseconds.
+pub fn to_webvtt(cues: &[Cue], offset: i64) -> String {
+    let mut output = String::from("WEBVTT\n\n");
+    for cue in cues {
+        let (Some(start), Some(end)) = (cue.start.checked_add(offset), cue.end.checked_add(offset))
+        else {
+            continue;
+        };
+        if end <= 0 {
+            continue;
+        }
+        output.push_str(&format!(
+            "{} --> {}{}\n{}\n\n",
+            format_timestamp(start),
+            format_timestamp(end),
+            cue.settings,
+            cue.text
+        ));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "\u{feff}1\r\n00:00:01,500 --> 00:00:03,000\r\nHello\r\nthere\r\n\r\n2\r\n00:01:02,003 --> 01:00:00,000\r\nBye\r\n";
+
+    const ASS: &str = "[Script Info]
+Title: Test
+
+[V4+ Styles]
+Format: Name, Fontname
+Style: Default,Arial
+
+[Events]
+Format: Layer, Start, End, Style, Text
+Dialogue: 0,0:00:05.00,0:00:06.50,Default,Second, with a comma
+Dialogue: 0,0:00:01.25,0:00:02.00,Default,{\\i1}First{\\i0}\\Nline
+";
+
+    #[test]
+    fn converts_srt() {
+        let cues = parse(SRT, None).unwrap();
+        assert_eq!(
+            to_webvtt(&cues, 0),
+            "WEBVTT\n\n\
+            00:00:01.500 --> 00:00:03.000\nHello\nthere\n\n\
+            00:01:02.003 --> 01:00:00.000\nBye\n\n"
+        );
+    }
+
+    #[test]
+    fn converts_ass() {
+        let cues = parse(ASS, None).unwrap();
+        assert_eq!(
+            to_webvtt(&cues, 0),
+            "WEBVTT\n\n\
+            00:00:01.250 --> 00:00:02.000\nFirst\nline\n\n\
+            00:00:05.000 --> 00:00:06.500\nSecond, with a comma\n\n"
+        );
+    }
+
+    #[test]
+    fn keeps_vtt_settings() {
+        let input =
+            "WEBVTT\n\nNOTE a comment\n\nintro\n00:01.000 --> 00:02.000 align:start line:0\nHi\n";
+        assert_eq!(
+            to_webvtt(&parse(input, Some(Format::Vtt)).unwrap(), 0),
+            "WEBVTT\n\n00:00:01.000 --> 00:00:02.000 align:start line:0\nHi\n\n"
+        );
+    }
+
+    #[test]
+    fn shifts_by_offset() {
+        let cues = parse(SRT, None).unwrap();
+        assert_eq!(
+            to_webvtt(&cues, 2_000),
+            "WEBVTT\n\n\
+            00:00:03.500 --> 00:00:05.000\nHello\nthere\n\n\
+            00:01:04.003 --> 01:00:02.000\nBye\n\n"
+        );
+    }
+
+    #[test]
+    fn drops_cues_shifted_before_the_start() {
+        let cues = parse(SRT, None).unwrap();
+        assert_eq!(
+            to_webvtt(&cues, -2_000),
+            "WEBVTT\n\n\
+            00:00:00.000 --> 00:00:01.000\nHello\nthere\n\n\
+            00:01:00.003 --> 00:59:58.000\nBye\n\n"
+        );
+        assert_eq!(to_webvtt(&cues, -3_600_000), "WEBVTT\n\n");
+    }
+
+    #[test]
+    fn rejects_invalid_timings() {
+        assert!(parse("1\n00:00:01,000 --> soon\nHello\n", Some(Format::Srt)).is_err());
+        assert!(parse("1\nHello\n", Some(Format::Srt)).is_err());
+        assert!(parse(
+            "[Events]\nDialogue: 0,0:00:01.00,0:00:02.00,Default,Hi\n",
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        assert_eq!(parse_timestamp("1:02:03.4"), Some(3_723_400));
+        assert_eq!(parse_timestamp("100:00:00,000"), Some(360_000_000));
+        for invalid in [
+            "",
+            "1:60:00.000",
+            "00:00:-1.000",
+            "+1:00:00.000",
+            "00:00:01.-5",
+            "1:00:00:00.000",
+            "99999999999999999999:00:00.000",
+            "9223372036854775807:00.000",
+        ] {
+            assert_eq!(parse_timestamp(invalid), None, "{invalid:?}");
+        }
+        assert!(parse(
+            "1\n00:00:02,000 --> 00:00:01,000\nBackwards\n",
+            Some(Format::Srt)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn skips_cues_the_offset_overflows() {
+        let cues = vec![Cue {
+            start: i64::MAX - 1,
+            end: i64::MAX,
+            settings: String::new(),
+            text: String::from("Late"),
+        }];
+        assert_eq!(to_webvtt(&cues, MAX_OFFSET), "WEBVTT\n\n");
+    }
+}
@@ -0,0 +1,128 @@
+use {
+    crate::config::LogConfig,
+    serde::{Deserialize, Serialize},
+    tracing_subscriber::{
+        layer::{Layered, SubscriberExt},
+        reload,
+        util::SubscriberInitExt,
+        EnvFilter, Layer, Registry,
+    },
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the fields of the current spans.
+    Json,
+}
+
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// Changes which spans and events are recorded while the server is running.
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    pub fn current(&self) -> String {
+        self.0
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = parse_filter(directives)?;
+        self.0.reload(filter).map_err(|err| err.to_string())
+    }
+}
+
+/// Filter directives like `info,sync_play=debug`, as in `RUST_LOG`.
+pub fn parse_filter(directives: &str) -> Result<EnvFilter, String> {
+    EnvFilter::builder()
+        .parse(directives)
+        .map_err(|err| format!("Invalid log filter {directives:?}: {err}"))
+}
+
+/// Installed logging, flushes pending traces when shut down.
+pub struct Telemetry {
+    pub filter: LogFilter,
+    #[cfg(feature = "otlp")]
+    tracer: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer) = self.tracer {
+            if let Err(err) = tracer.shutdown() {
+                eprintln!("Unable to flush traces: {err}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber, `log` records from dependencies are forwarded to it.
+pub fn init(config: &LogConfig) -> Result<Telemetry, String> {
+    let filter = parse_filter(&config.level).map_err(|err| format!("log.level: {err}"))?;
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let output: Box<dyn Layer<Filtered> + Send + Sync> = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    #[cfg(feature = "otlp")]
+    let tracer = config
+        .otlp_endpoint
+        .as_deref()
+        .map(otlp::tracer)
+        .transpose()?;
+    #[cfg(feature = "otlp")]
+    let otlp = tracer.as_ref().map(|tracer| {
+        use opentelemetry::trace::TracerProvider;
+        tracing_opentelemetry::layer().with_tracer(tracer.tracer(env!("CARGO_PKG_NAME")))
+    });
+    #[cfg(not(feature = "otlp"))]
+    let otlp: Option<tracing_subscriber::layer::Identity> = None;
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(otlp)
+        .init();
+
+    Ok(Telemetry {
+        filter: LogFilter(handle),
+        #[cfg(feature = "otlp")]
+        tracer,
+    })
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use {
+        opentelemetry_otlp::{SpanExporter, WithExportConfig},
+        opentelemetry_sdk::{trace::SdkTracerProvider, Resource},
+    };
+
+    /// Exports spans over OTLP/HTTP, e.g. to a local collector on `http://localhost:4318`.
+    pub fn tracer(endpoint: &str) -> Result<SdkTracerProvider, String> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .map_err(|err| format!("Unable to create the OTLP exporter: {err}"))?;
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build())
+    }
+}
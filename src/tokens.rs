@@ -0,0 +1,239 @@
+use {
+    crate::{
+        admin,
+        clock::timestamp,
+        error::{bad_request, forbidden, not_found, ToErr},
+        storage,
+        user::SessionUser,
+        AppData,
+    },
+    actix_session::Session,
+    actix_web::{delete, get, post, web, Error, HttpResponse, Responder},
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    rand::RngCore,
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        fmt,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+const FILE: &str = "tokens.json";
+const PREFIX: &str = "sp_";
+
+/// What a personal access token may be used for. Session cookies may do everything.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// List and view rooms, their subtitles and events.
+    #[serde(rename = "rooms:read")]
+    RoomsRead,
+    /// Create rooms and change their settings and subtitles.
+    #[serde(rename = "rooms:write")]
+    RoomsWrite,
+    /// Join a room's socket and control playback and media.
+    #[serde(rename = "rooms:control")]
+    RoomsControl,
+    /// Use the `/api/admin` routes, only for admins.
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RoomsRead => write!(f, "rooms:read"),
+            Self::RoomsWrite => write!(f, "rooms:write"),
+            Self::RoomsControl => write!(f, "rooms:control"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: u32,
+    pub user_id: u32,
+    /// The user's name when the token was created, used as the display name for the token.
+    pub user_name: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Milliseconds since the unix epoch.
+    pub created: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// Only the hash is kept, the secret is shown once when the token is created.
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct TokensFile {
+    tokens: Vec<StoredToken>,
+}
+
+fn hash(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Personal access tokens, accepted as `Authorization: Bearer <token>`.
+pub struct Tokens {
+    path: PathBuf,
+    file: Mutex<TokensFile>,
+}
+
+impl Tokens {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(FILE);
+        Ok(Self {
+            file: Mutex::new(storage::load(&path)?),
+            path,
+        })
+    }
+
+    /// Creates a token, returning it together with its secret, which isn't stored.
+    pub fn create(
+        &self,
+        user: &SessionUser,
+        name: String,
+        scopes: Vec<Scope>,
+    ) -> std::io::Result<(ApiToken, String)> {
+        let mut bytes = [0; 30];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let secret = format!("{PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+
+        let mut file = self.file.lock().unwrap();
+        let token = ApiToken {
+            id: file
+                .tokens
+                .iter()
+                .map(|x| x.token.id + 1)
+                .max()
+                .unwrap_or(1),
+            user_id: user.id,
+            user_name: user.name.clone(),
+            name,
+            scopes,
+            created: timestamp(),
+        };
+        file.tokens.push(StoredToken {
+            token: token.clone(),
+            hash: hash(&secret),
+        });
+        storage::save(&self.path, &*file)?;
+        Ok((token, secret))
+    }
+
+    pub fn list(&self, user_id: u32) -> Vec<ApiToken> {
+        let file = self.file.lock().unwrap();
+        file.tokens
+            .iter()
+            .filter(|x| x.token.user_id == user_id)
+            .map(|x| x.token.clone())
+            .collect()
+    }
+
+    pub fn revoke(&self, user_id: u32, id: u32) -> std::io::Result<bool> {
+        let mut file = self.file.lock().unwrap();
+        let count = file.tokens.len();
+        file.tokens
+            .retain(|x| !(x.token.user_id == user_id && x.token.id == id));
+        if file.tokens.len() == count {
+            return Ok(false);
+        }
+        storage::save(&self.path, &*file)?;
+        Ok(true)
+    }
+
+    pub fn find(&self, secret: &str) -> Option<ApiToken> {
+        if !secret.starts_with(PREFIX) {
+            return None;
+        }
+        let hash = hash(secret);
+        let file = self.file.lock().unwrap();
+        file.tokens
+            .iter()
+            .find(|x| x.hash == hash)
+            .map(|x| x.token.clone())
+    }
+}
+
+/// Tokens are managed with a browser session only, so a leaked token can't mint new ones.
+fn session_user(session: &Session, data: &AppData) -> Result<SessionUser, Error> {
+    SessionUser::from_session_non_guest(session, data, "Guests can't create tokens")
+}
+
+#[get("/api/tokens")]
+async fn list(data: web::Data<AppData>, session: Session) -> Result<impl Responder, Error> {
+    let user = session_user(&session, &data)?;
+    Ok(web::Json(data.tokens.list(user.id)))
+}
+
+#[derive(Deserialize)]
+struct NewToken {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize)]
+struct CreatedToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// Only ever shown once.
+    secret: String,
+}
+
+#[post("/api/tokens")]
+async fn create(
+    data: web::Data<AppData>,
+    session: Session,
+    body: web::Json<NewToken>,
+) -> Result<impl Responder, Error> {
+    let user = session_user(&session, &data)?;
+    let body = body.into_inner();
+    if body.name.trim().is_empty() {
+        return Err(bad_request("invalid_token_name", "Tokens need a name"));
+    }
+    if body.scopes.is_empty() {
+        return Err(bad_request(
+            "missing_scopes",
+            "Tokens need at least one scope",
+        ));
+    }
+    if body.scopes.contains(&Scope::Admin) && !admin::is_admin(&data, user.id) {
+        return Err(forbidden(
+            "admins_only",
+            "Only admins can create admin tokens",
+        ));
+    }
+
+    let (token, secret) = data
+        .tokens
+        .create(&user, body.name.trim().to_owned(), body.scopes)
+        .to_err()?;
+    Ok(HttpResponse::Created().json(CreatedToken { token, secret }))
+}
+
+#[delete("/api/tokens/{id}")]
+async fn revoke(
+    data: web::Data<AppData>,
+    session: Session,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let user = session_user(&session, &data)?;
+    match data.tokens.revoke(user.id, id.into_inner()).to_err()? {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(not_found("token_not_found", "Token not found")),
+    }
+}
+
+pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(create);
+    cfg.service(revoke);
+}
@@ -1,119 +1,302 @@
 use {
-    crate::error::ToErr,
-    actix_session::Session,
+    crate::{
+        auth::{AuthProvider, DevProvider},
+        error::{
+            bad_request, conflict, forbidden, internal, not_found, service_unavailable,
+            unauthorized, ToErr,
+        },
+        redirect,
+        tokens::Scope,
+    },
+    actix_session::{Session, SessionExt},
     actix_web::{
-        error::ErrorUnauthorized,
+        dev::Payload,
         get,
         http::header,
         post,
         web::{self},
-        Error, HttpRequest, HttpResponse, Responder,
+        Error, FromRequest, HttpRequest, HttpResponse, Responder,
     },
+    futures_util::future::{ready, Ready},
+    rand::Rng,
     serde::{Deserialize, Serialize},
+    std::{collections::HashMap, time::Instant},
 };
 
 use crate::AppData;
 
-#[derive(Serialize, Deserialize)]
+/// Guest ids are random from here up, so they don't collide with real users or guests from
+/// before a restart. Ids of guests that still have a session are skipped.
+const GUEST_FIRST_ID: u32 = 1 << 31;
+
+/// Display names of guests, local accounts and preferences, expected to be trimmed.
+pub fn valid_name(name: &str) -> bool {
+    (1..=32).contains(&name.chars().count()) && !name.chars().any(char::is_control)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct SessionUser {
     pub id: u32,
     pub name: String,
+    #[serde(default)]
+    pub guest: bool,
+    /// What the user may do when authenticated with a token, `None` for session cookies.
+    #[serde(skip)]
+    pub scopes: Option<Vec<Scope>>,
 }
 
-impl std::convert::TryFrom<&Session> for SessionUser {
-    type Error = Error;
-    fn try_from(session: &Session) -> Result<SessionUser, Error> {
+impl SessionUser {
+    /// The user logged in with `session`.
+    pub fn from_session(session: &Session, data: &AppData) -> Result<Self, Error> {
         match session.get::<SessionUser>("user")? {
-            Some(id) => Ok(id),
-            None => Err(ErrorUnauthorized("Not logged in")),
+            Some(user) => user.check_ban(data),
+            None => Err(unauthorized("not_logged_in", "Not logged in")),
+        }
+    }
+
+    /// Like [`Self::from_session`], but guests are rejected with `message`.
+    pub fn from_session_non_guest(
+        session: &Session,
+        data: &AppData,
+        message: &'static str,
+    ) -> Result<Self, Error> {
+        let user = Self::from_session(session, data)?;
+        match user.guest {
+            true => Err(forbidden("guests_not_allowed", message)),
+            false => Ok(user),
         }
     }
+
+    fn check_ban(self, data: &AppData) -> Result<Self, Error> {
+        match data.bans.is_banned(self.id) {
+            true => Err(forbidden("banned", "You have been banned")),
+            false => Ok(self),
+        }
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), Error> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => Err(forbidden(
+                "missing_scope",
+                format!("Token is missing the {scope} scope"),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Accepts a personal access token as `Authorization: Bearer`, or the session cookie.
+impl FromRequest for SessionUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let Some(data) = req.app_data::<web::Data<AppData>>() else {
+            return ready(Err(internal("internal", "App data is not configured")));
+        };
+        let authorization = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        ready(match authorization {
+            Some(value) => match value.strip_prefix("Bearer ") {
+                Some(secret) => match data.tokens.find(secret.trim()) {
+                    Some(token) => SessionUser {
+                        id: token.user_id,
+                        name: token.user_name,
+                        guest: false,
+                        scopes: Some(token.scopes),
+                    }
+                    .check_ban(data),
+                    None => Err(unauthorized("invalid_token", "Invalid token")),
+                },
+                None => Err(unauthorized(
+                    "unsupported_auth_scheme",
+                    "Unsupported authorization scheme",
+                )),
+            },
+            None => SessionUser::from_session(&req.get_session(), data),
+        })
+    }
 }
 
+/// `?path=` is where to go after logging in.
 #[get("/auth")]
-async fn auth_redirect() -> impl Responder {
-    HttpResponse::PermanentRedirect()
-        .append_header((
-            header::LOCATION,
-            AppData::get().authentication_service.get_redirect_url(),
-        ))
-        .finish()
+async fn auth_redirect(
+    data: web::Data<AppData>,
+    session: Session,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, Error> {
+    let provider = data.auth_providers.default_provider().ok_or_else(|| {
+        service_unavailable("no_auth_provider", "No authentication provider configured")
+    })?;
+    redirect::remember(&session, query.get("path").map(String::as_str))?;
+    // `/auth?as=<id>&name=<name>` logs in directly, so tests don't have to go through the picker
+    if provider.name() == DevProvider::NAME && query.contains_key("as") {
+        return login(&data, provider, &session, &query).await;
+    }
+    login_redirect(provider, &session).await
+}
+
+#[get("/auth/providers")]
+async fn list_providers(data: web::Data<AppData>) -> impl Responder {
+    HttpResponse::Ok().json(data.auth_providers.info())
 }
 
 #[derive(Deserialize)]
-struct AuthServerId {
-    id: Option<u64>,
+struct RedirectQuery {
+    path: Option<String>,
 }
 
+#[get("/auth/providers/{name}")]
+async fn provider_redirect(
+    data: web::Data<AppData>,
+    session: Session,
+    name: web::Path<String>,
+    query: web::Query<RedirectQuery>,
+) -> Result<impl Responder, Error> {
+    redirect::remember(&session, query.path.as_deref())?;
+    login_redirect(provider(&data, &name)?, &session).await
+}
+
+#[get("/auth/providers/{name}/callback")]
+async fn provider_callback(
+    data: web::Data<AppData>,
+    session: Session,
+    name: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<impl Responder, Error> {
+    login(&data, provider(&data, &name)?, &session, &query).await
+}
+
+/// The authentication service is registered with this callback url.
 #[get("/auth/auth_server")]
 async fn auth_server_login(
-    req: HttpRequest,
+    data: web::Data<AppData>,
     session: Session,
-    id: web::Query<AuthServerId>,
+    query: web::Query<HashMap<String, String>>,
 ) -> Result<impl Responder, Error> {
-    match id.into_inner().id {
-        Some(id) => {
-            let user = AppData::get()
-                .authentication_service
-                .query_authentication_request(id)
-                .await
-                .to_err()?;
-
-            session
-                .insert(
-                    "user",
-                    SessionUser {
-                        id: user.id,
-                        name: user.display_name,
-                    },
-                )
-                .to_err()?;
-
-            let mut response = HttpResponse::Found();
-            match req.cookie("path") {
-                Some(cookie) => response.append_header(("location", cookie.value())),
-                None => response.append_header(("location", "/")),
-            };
-            Ok(response.finish())
+    login(&data, provider(&data, "auth_server")?, &session, &query).await
+}
+
+fn provider<'a>(data: &'a AppData, name: &str) -> Result<&'a dyn AuthProvider, Error> {
+    data.auth_providers.get(name).ok_or_else(|| {
+        not_found(
+            "provider_not_found",
+            format!("Authentication provider {name:?} not found"),
+        )
+    })
+}
+
+async fn login_redirect(
+    provider: &dyn AuthProvider,
+    session: &Session,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Found()
+        .append_header((header::LOCATION, provider.login_url(session).await?))
+        .finish())
+}
+
+async fn login(
+    data: &AppData,
+    provider: &dyn AuthProvider,
+    session: &Session,
+    query: &HashMap<String, String>,
+) -> Result<HttpResponse, Error> {
+    let start = Instant::now();
+    let user = provider.callback(&data.users, query, session).await;
+    data.metrics
+        .auth_callback
+        .with_label_values(&[provider.name()])
+        .observe(start.elapsed().as_secs_f64());
+    match user? {
+        Some(user) => {
+            // a new session key, so one planted before logging in is worthless
+            session.renew();
+            session.insert("user", user).to_err()?;
+            Ok(HttpResponse::Found()
+                .append_header((header::LOCATION, redirect::take(session)))
+                .finish())
         }
-        None => Ok(HttpResponse::Found()
-            .append_header((
-                "location",
-                AppData::get().authentication_service.get_redirect_url(),
-            ))
-            .finish()),
+        None => login_redirect(provider, session).await,
     }
 }
 
+#[derive(Deserialize)]
+struct NewGuest {
+    name: String,
+}
+
+#[post("/auth/guest")]
+async fn guest_login(
+    data: web::Data<AppData>,
+    session: Session,
+    body: web::Json<NewGuest>,
+) -> Result<impl Responder, Error> {
+    if session.get::<SessionUser>("user")?.is_some() {
+        return Err(conflict(
+            "already_logged_in",
+            "Log out before continuing as a guest",
+        ));
+    }
+    let name = body.name.trim();
+    if !valid_name(name) {
+        return Err(bad_request(
+            "invalid_name",
+            "Names must be 1 to 32 characters long, without control characters",
+        ));
+    }
+
+    let id = loop {
+        let id = rand::thread_rng().gen_range(GUEST_FIRST_ID..=u32::MAX);
+        if !data.sessions.has_user(id) {
+            break id;
+        }
+    };
+    let user = SessionUser {
+        id,
+        name: name.to_owned(),
+        guest: true,
+        scopes: None,
+    };
+    session.renew();
+    session.insert("user", &user).to_err()?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
 #[get("/auth/user")]
-async fn get_user(session: Session) -> Result<impl Responder, Error> {
-    SessionUser::try_from(&session).map(|user| HttpResponse::Ok().json(user))
+async fn get_user(data: web::Data<AppData>, session: Session) -> Result<impl Responder, Error> {
+    SessionUser::from_session(&session, &data).map(|user| HttpResponse::Ok().json(user))
 }
 
 #[post("/auth/logout")]
 async fn logout(session: Session) -> Result<impl Responder, Error> {
-    session.remove("user");
+    session.purge();
     Ok(HttpResponse::Ok().finish())
 }
 
-#[get("/auth/users/{id}/picture")]
-async fn get_profile_picture(id: web::Path<i32>) -> Result<impl Responder, Error> {
-    let id = id.into_inner();
-    Ok(HttpResponse::PermanentRedirect()
-        .append_header((
-            header::LOCATION,
-            format!(
-                "https://{}/api/profiles/{id}/picture",
-                AppData::get().authentication_service.host()
-            ),
-        ))
-        .finish())
-}
-
 pub fn init(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(auth_redirect);
+    cfg.service(list_providers);
+    cfg.service(provider_redirect);
+    cfg.service(provider_callback);
     cfg.service(auth_server_login);
+    cfg.service(guest_login);
     cfg.service(get_user);
     cfg.service(logout);
-    cfg.service(get_profile_picture);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_names() {
+        for valid in ["A", "Alice Smith", "名前", &"a".repeat(32)] {
+            assert!(valid_name(valid), "{valid:?}");
+        }
+        for invalid in ["", &"a".repeat(33), "tab\there", "line\nbreak", "\u{7f}"] {
+            assert!(!valid_name(invalid), "{invalid:?}");
+        }
+    }
 }
@@ -0,0 +1,259 @@
+use {
+    crate::{
+        error::{conflict, service_unavailable, ToErr},
+        storage,
+    },
+    actix_web::Error,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+/// Ids handed out by the server start here, so they don't collide with the
+/// authentication service's own user ids.
+const FIRST_ID: u32 = 1 << 30;
+const FILE: &str = "users.json";
+
+/// A user of a provider that doesn't have numeric ids of its own.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserRecord {
+    pub id: u32,
+    pub provider: String,
+    pub subject: String,
+    pub name: String,
+    pub picture: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    #[default]
+    User,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct UsersFile {
+    users: Vec<UserRecord>,
+    /// Keyed by user id, so users of any provider can have a role.
+    #[serde(default)]
+    roles: HashMap<u32, Role>,
+    /// Users whose role came from their provider's groups, which may take it away again.
+    #[serde(default)]
+    provider_roles: HashSet<u32>,
+}
+
+#[derive(Debug)]
+pub enum UserError {
+    /// The id belongs to a user of another provider.
+    IdTaken,
+    /// Every id from `FIRST_ID` up has been handed out.
+    NoIdsLeft,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IdTaken => write!(f, "This user id belongs to another login provider"),
+            Self::NoIdsLeft => write!(f, "No user ids left"),
+            Self::Io(err) => write!(f, "Unable to save users: {err}"),
+        }
+    }
+}
+
+impl ToErr for UserError {
+    type Return = Error;
+
+    fn to_err(self) -> Self::Return {
+        match self {
+            Self::IdTaken => conflict("user_id_taken", self.to_string()),
+            Self::NoIdsLeft => service_unavailable("no_user_ids_left", self.to_string()),
+            Self::Io(err) => err.to_err(),
+        }
+    }
+}
+
+pub struct Users {
+    path: PathBuf,
+    file: Mutex<UsersFile>,
+}
+
+impl Users {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(FILE);
+        Ok(Self {
+            file: Mutex::new(storage::load(&path)?),
+            path,
+        })
+    }
+
+    /// Finds the user with `subject` at `provider`, creating them if they're new.
+    /// The name and picture are updated to the provider's latest values.
+    pub fn resolve(
+        &self,
+        provider: &str,
+        subject: &str,
+        name: String,
+        picture: Option<String>,
+    ) -> Result<UserRecord, UserError> {
+        let mut file = self.file.lock().unwrap();
+        let record = match file
+            .users
+            .iter_mut()
+            .find(|user| user.provider == provider && user.subject == subject)
+        {
+            Some(user) if user.name == name && user.picture == picture => return Ok(user.clone()),
+            Some(user) => {
+                user.name = name;
+                user.picture = picture;
+                user.clone()
+            }
+            None => {
+                // users remembered with their provider's own ids may be below `FIRST_ID`
+                let id = match file.users.iter().map(|user| user.id).max() {
+                    Some(max) if max >= FIRST_ID => {
+                        max.checked_add(1).ok_or(UserError::NoIdsLeft)?
+                    }
+                    _ => FIRST_ID,
+                };
+                let user = UserRecord {
+                    id,
+                    provider: provider.to_owned(),
+                    subject: subject.to_owned(),
+                    name,
+                    picture,
+                };
+                file.users.push(user.clone());
+                user
+            }
+        };
+        storage::save(&self.path, &*file).map_err(UserError::Io)?;
+        Ok(record)
+    }
+
+    /// Records a user of a provider with numeric ids of its own, so their
+    /// picture and name can be looked up later. Fails if another provider has the id.
+    pub fn remember(&self, provider: &str, id: u32, name: &str) -> Result<(), UserError> {
+        let mut file = self.file.lock().unwrap();
+        match file.users.iter_mut().find(|user| user.id == id) {
+            Some(user) if user.provider != provider => {
+                tracing::warn!(
+                    user_id = id,
+                    provider,
+                    other_provider = user.provider,
+                    "Refused login with a user id of another provider"
+                );
+                return Err(UserError::IdTaken);
+            }
+            Some(user) if user.name == name => return Ok(()),
+            Some(user) => user.name = name.to_owned(),
+            None => file.users.push(UserRecord {
+                id,
+                provider: provider.to_owned(),
+                subject: id.to_string(),
+                name: name.to_owned(),
+                picture: None,
+            }),
+        }
+        storage::save(&self.path, &*file).map_err(UserError::Io)
+    }
+
+    pub fn get(&self, id: u32) -> Option<UserRecord> {
+        let file = self.file.lock().unwrap();
+        file.users.iter().find(|user| user.id == id).cloned()
+    }
+
+    pub fn role(&self, id: u32) -> Role {
+        let file = self.file.lock().unwrap();
+        file.roles.get(&id).copied().unwrap_or_default()
+    }
+
+    /// Sets a role by hand, providers won't change it anymore.
+    pub fn set_role(&self, id: u32, role: Role) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let from_provider = file.provider_roles.remove(&id);
+        let previous = match role {
+            Role::User => file.roles.remove(&id),
+            role => file.roles.insert(id, role),
+        };
+        match previous.unwrap_or_default() == role && !from_provider {
+            true => Ok(()),
+            false => storage::save(&self.path, &*file),
+        }
+    }
+
+    /// Applies the admin role according to the provider's groups. Admins only
+    /// lose the role if the provider granted it, not if it was set by hand.
+    pub fn set_provider_role(&self, id: u32, admin: bool) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let current = file.roles.get(&id).copied().unwrap_or_default();
+        match (admin, current) {
+            (true, Role::User) => {
+                file.roles.insert(id, Role::Admin);
+                file.provider_roles.insert(id);
+            }
+            (false, Role::Admin) if file.provider_roles.remove(&id) => {
+                file.roles.remove(&id);
+            }
+            _ => return Ok(()),
+        }
+        storage::save(&self.path, &*file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(name: &str) -> Users {
+        let dir =
+            std::env::temp_dir().join(format!("sync-play-users-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Users::load(&dir).unwrap()
+    }
+
+    #[test]
+    fn allocates_ids_from_first_id() {
+        let users = users("allocate");
+        users.remember("auth_server", 7, "Alice").unwrap();
+        let bob = users
+            .resolve("oidc", "bob", String::from("Bob"), None)
+            .unwrap();
+        let carol = users
+            .resolve("oidc", "carol", String::from("Carol"), None)
+            .unwrap();
+        assert_eq!((bob.id, carol.id), (FIRST_ID, FIRST_ID + 1));
+        let bob_again = users
+            .resolve("oidc", "bob", String::from("Bob"), None)
+            .unwrap();
+        assert_eq!(bob_again.id, bob.id);
+    }
+
+    #[test]
+    fn refuses_ids_of_other_providers() {
+        let users = users("taken");
+        let bob = users
+            .resolve("oidc", "bob", String::from("Bob"), None)
+            .unwrap();
+        assert!(matches!(
+            users.remember("auth_server", bob.id, "Mallory"),
+            Err(UserError::IdTaken)
+        ));
+        assert_eq!(users.get(bob.id).unwrap().name, "Bob");
+    }
+
+    #[test]
+    fn fails_when_ids_run_out() {
+        let users = users("exhausted");
+        users.remember("auth_server", u32::MAX, "Last").unwrap();
+        assert!(matches!(
+            users.resolve("oidc", "bob", String::from("Bob"), None),
+            Err(UserError::NoIdsLeft)
+        ));
+    }
+}